@@ -0,0 +1,110 @@
+use crate::constants;
+use crate::mqtt;
+
+use simple_error::bail;
+use std::error::Error;
+
+// Results of the connectivity checks of --check --connect
+pub struct Report {
+    results: Vec<(String, Result<(), String>)>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report {
+            results: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, item: &str, result: Result<(), Box<dyn Error>>) {
+        self.results
+            .push((item.to_string(), result.map_err(|e| e.to_string())));
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+
+    pub fn print(&self) {
+        for (item, result) in self.results.iter() {
+            match result {
+                Ok(_) => println!("PASS  {}", item),
+                Err(e) => println!("FAIL  {}: {}", item, e),
+            };
+        }
+        let failed = self.results.iter().filter(|(_, r)| r.is_err()).count();
+        println!();
+        println!("{} checks, {} failed", self.results.len(), failed);
+    }
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Topic used for test messages, below the configured topic
+pub fn test_topic(topic: &str) -> String {
+    format!("{}/{}", topic, constants::CHECK_TOPIC)
+}
+
+pub fn is_test_topic(topic: &str) -> bool {
+    topic.ends_with(&format!("/{}", constants::CHECK_TOPIC))
+}
+
+// Connect to the broker and either publish a test message on a sub-topic or subscribe to the topic
+pub fn mqtt(cfg: &mqtt::MQTT, publish: bool, report: &mut Report) {
+    let item = format!("connect to MQTT broker {}", cfg.broker);
+
+    // Don't kick out a running instance using the same client ID, and don't leave a session behind
+    let mut check_cfg = cfg.clone();
+    check_cfg.client_id = mqtt::random_client_id();
+    check_cfg.clean_session = true;
+
+    let client = match mqtt_connect(&check_cfg) {
+        Ok(v) => {
+            report.add(&item, Ok(()));
+            v
+        }
+        Err(e) => {
+            report.add(&item, Err(e));
+            return;
+        }
+    };
+
+    if publish {
+        let topic = test_topic(&cfg.topic);
+        let msg = paho_mqtt::message::Message::new(&topic, Vec::new(), cfg.qos);
+        report.add(
+            &format!("publish test message on topic {}", topic),
+            client.publish(msg).map_err(|e| e.into()),
+        );
+    } else {
+        report.add(
+            &format!("subscribe to topic {}", cfg.topic),
+            mqtt_subscribe(&check_cfg, &client),
+        );
+    }
+
+    let _ = client.disconnect(None);
+}
+
+fn mqtt_connect(cfg: &mqtt::MQTT) -> Result<paho_mqtt::Client, Box<dyn Error>> {
+    let conn = mqtt::connection_builder(cfg)?;
+    let client = mqtt::client_builder(cfg)?;
+    client.connect(conn)?;
+    Ok(client)
+}
+
+fn mqtt_subscribe(cfg: &mqtt::MQTT, client: &paho_mqtt::Client) -> Result<(), Box<dyn Error>> {
+    let response = client.subscribe(&cfg.topic, cfg.qos)?;
+    // the broker signals a rejected subscription by a granted QoS of 0x80
+    if let Some(qos) = response.subscribe_response() {
+        if qos >= 0x80 {
+            bail!("subscription was rejected by the broker");
+        }
+    }
+    let _ = client.unsubscribe(&cfg.topic);
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use serde::Deserialize;
+use simple_error::bail;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+// Holds passwords and other sensitive values, Debug output never shows the value
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(s: String) -> Self {
+        Secret(s)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            write!(f, "\"\"")
+        } else {
+            write!(f, "\"<redacted>\"")
+        }
+    }
+}
+
+// Read a configuration file and replace ${VAR} in string values by the value of the environment
+// variable VAR. ${VAR:-default} uses default if VAR is unset or empty, $${ is replaced by a literal ${.
+// Only parsed values are expanded, so comments are ignored and the YAML syntax can't be changed by
+// the content of a variable
+pub fn read(f: &str) -> Result<serde_yaml::Value, Box<dyn Error>> {
+    let raw = fs::read_to_string(f)?;
+    let mut parsed: serde_yaml::Value = serde_yaml::from_str(&raw)?;
+    expand_value(&mut parsed)?;
+    Ok(parsed)
+}
+
+fn expand_value(value: &mut serde_yaml::Value) -> Result<(), Box<dyn Error>> {
+    match value {
+        serde_yaml::Value::String(s) => *s = expand_env(s)?,
+        serde_yaml::Value::Sequence(seq) => {
+            for v in seq.iter_mut() {
+                expand_value(v)?;
+            }
+        }
+        // keys are left as they are
+        serde_yaml::Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                expand_value(v)?;
+            }
+        }
+        serde_yaml::Value::Tagged(t) => expand_value(&mut t.value)?,
+        serde_yaml::Value::Null | serde_yaml::Value::Bool(_) | serde_yaml::Value::Number(_) => {}
+    };
+    Ok(())
+}
+
+fn expand_env(raw: &str) -> Result<String, Box<dyn Error>> {
+    let mut result = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if rest.starts_with("$${") {
+            result.push_str("${");
+            rest = &rest[3..];
+        } else if let Some(var) = rest.strip_prefix("${") {
+            let end = match var.find('}') {
+                Some(v) => v,
+                None => bail!("unterminated variable reference {}", rest),
+            };
+            let (name, default) = match var[..end].split_once(":-") {
+                Some((n, d)) => (n, Some(d)),
+                None => (&var[..end], None),
+            };
+            if name.is_empty() {
+                bail!("empty variable name in {}", &rest[..end + 3]);
+            }
+            match (std::env::var(name), default) {
+                (Ok(v), Some(d)) if v.is_empty() => result.push_str(d),
+                (Ok(v), _) => result.push_str(&v),
+                (Err(std::env::VarError::NotPresent), Some(d)) => result.push_str(d),
+                (Err(e), _) => bail!("can't read environment variable {} - {}", name, e),
+            };
+            rest = &var[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+// Use the content of file as value of the secret if file is set. Setting both is an error
+pub fn secret_from_file(secret: &mut Secret, file: &str, name: &str) -> Result<(), Box<dyn Error>> {
+    if file.is_empty() {
+        return Ok(());
+    }
+    if !secret.is_empty() {
+        bail!("only one of {} and {}_file can be set", name, name);
+    }
+
+    let content = match fs::read_to_string(file) {
+        Ok(v) => v,
+        Err(e) => bail!("can't read {}_file {} - {}", name, file, e),
+    };
+    // files created by editors or echo usually end with a newline that isn't part of the secret
+    *secret = Secret(content.trim_end_matches(['\r', '\n']).to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, content: &str) -> String {
+        let f = std::env::temp_dir().join(format!(
+            "prometheus-mqtt-transport-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::write(&f, content).unwrap();
+        f.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        std::env::set_var("P2M_TEST_EXPAND", "value");
+        assert_eq!(
+            expand_env("a ${P2M_TEST_EXPAND} b").unwrap(),
+            "a value b".to_string()
+        );
+        assert_eq!(
+            expand_env("${P2M_TEST_EXPAND}${P2M_TEST_EXPAND}").unwrap(),
+            "valuevalue".to_string()
+        );
+        assert_eq!(
+            expand_env("no variables").unwrap(),
+            "no variables".to_string()
+        );
+    }
+
+    #[test]
+    fn defaults_are_used_for_unset_or_empty_variables() {
+        std::env::remove_var("P2M_TEST_UNSET");
+        std::env::set_var("P2M_TEST_EMPTY", "");
+        std::env::set_var("P2M_TEST_SET", "set");
+        assert_eq!(
+            expand_env("${P2M_TEST_UNSET:-default}").unwrap(),
+            "default".to_string()
+        );
+        assert_eq!(
+            expand_env("${P2M_TEST_EMPTY:-default}").unwrap(),
+            "default".to_string()
+        );
+        assert_eq!(
+            expand_env("${P2M_TEST_SET:-default}").unwrap(),
+            "set".to_string()
+        );
+        assert_eq!(expand_env("${P2M_TEST_UNSET:-}").unwrap(), String::new());
+    }
+
+    #[test]
+    fn missing_variables_are_an_error() {
+        std::env::remove_var("P2M_TEST_MISSING");
+        assert!(expand_env("${P2M_TEST_MISSING}").is_err());
+        assert!(expand_env("${P2M_TEST_MISSING").is_err());
+        assert!(expand_env("${}").is_err());
+        assert!(expand_env("${:-default}").is_err());
+    }
+
+    #[test]
+    fn dollar_escapes() {
+        assert_eq!(
+            expand_env("$${P2M_TEST_MISSING}").unwrap(),
+            "${P2M_TEST_MISSING}".to_string()
+        );
+        assert_eq!(expand_env("a$b $ $$").unwrap(), "a$b $ $$".to_string());
+    }
+
+    #[test]
+    fn only_values_are_expanded() {
+        std::env::set_var("P2M_TEST_QUOTE", "it's: \"quoted\"\n# not a comment");
+        std::env::remove_var("P2M_TEST_COMMENT");
+        let f = temp_file(
+            "expand",
+            "# ${P2M_TEST_COMMENT} in a comment\n\
+             password: '${P2M_TEST_QUOTE}'\n\
+             list:\n  - ${P2M_TEST_QUOTE}\n\
+             ${P2M_TEST_COMMENT}: 1\n",
+        );
+        let parsed = read(&f);
+        fs::remove_file(&f).unwrap();
+        let parsed = parsed.unwrap();
+
+        assert_eq!(
+            parsed["password"].as_str(),
+            Some("it's: \"quoted\"\n# not a comment")
+        );
+        assert_eq!(
+            parsed["list"][0].as_str(),
+            Some("it's: \"quoted\"\n# not a comment")
+        );
+        assert_eq!(parsed["${P2M_TEST_COMMENT}"].as_u64(), Some(1));
+    }
+
+    #[test]
+    fn secrets_from_file() {
+        let f = temp_file("secret", "S3cr37\n");
+
+        let mut secret = Secret::default();
+        secret_from_file(&mut secret, &f, "password").unwrap();
+        assert_eq!(secret.expose(), "S3cr37");
+
+        // setting both is an error
+        assert!(secret_from_file(&mut secret, &f, "password").is_err());
+
+        // no file keeps the value
+        secret_from_file(&mut secret, "", "password").unwrap();
+        assert_eq!(secret.expose(), "S3cr37");
+
+        fs::remove_file(&f).unwrap();
+        let mut secret = Secret::default();
+        assert!(secret_from_file(&mut secret, &f, "password").is_err());
+        assert_eq!(
+            format!("{:?}", Secret::from("x".to_string())),
+            "\"<redacted>\""
+        );
+    }
+}
@@ -3,3 +3,24 @@ pub const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 pub const DEFAULT_MQTT_TIMEOUT: u64 = 15;
 pub const DEFAULT_MQTT_RECONNECT_TIMEOUT: u64 = 300;
 pub const MAXIMAL_CLIENT_ID_LENGTH: usize = 23;
+pub const SHUTDOWN_TIMEOUT: u64 = 10;
+pub const CHECK_TOPIC: &str = "prom2mqtt-check";
+
+pub const DEFAULT_SYSLOG_FACILITY: &str = "daemon";
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+pub const HTTP_HEALTHY_PATH: &str = "/-/healthy";
+pub const HTTP_READY_PATH: &str = "/-/ready";
+pub const HTTP_RELOAD_PATH: &str = "/-/reload";
+pub const HTTP_HEALTHY: &str = "Healthy\n";
+pub const HTTP_READY: &str = "Ready\n";
+pub const HTTP_RELOADED: &str = "Configuration reloaded\n";
+pub const HTTP_NOT_CONNECTED: &str = "Not connected to MQTT broker\n";
+
+pub const WEB_AUTH_CACHE_SIZE: usize = 1024;
+pub const WEB_CLIENT_AUTH_NONE: &str = "NoClientCert";
+pub const WEB_CLIENT_AUTH_VERIFY_IF_GIVEN: &str = "VerifyClientCertIfGiven";
+pub const WEB_CLIENT_AUTH_REQUIRE_AND_VERIFY: &str = "RequireAndVerifyClientCert";
+pub const WEB_TLS_HANDSHAKE_TIMEOUT: u64 = 10;
+pub const WEB_TLS_BACKEND_SOCKET: &str = "http.sock";
+pub const WEB_PRIVATE_DIR_ATTEMPTS: usize = 16;
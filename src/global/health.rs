@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Connection state shared between the MQTT and the HTTP threads
+#[derive(Debug, Default)]
+pub struct Health {
+    connected: AtomicBool,
+    // exporter: subscribed to the topic, fetcher: at least one message published
+    operational: AtomicBool,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set_connected(&self, v: bool) {
+        self.connected.store(v, Ordering::SeqCst);
+    }
+
+    pub fn set_operational(&self, v: bool) {
+        self.operational.store(v, Ordering::SeqCst);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn is_operational(&self) -> bool {
+        self.operational.load(Ordering::SeqCst)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.is_connected() && self.is_operational()
+    }
+}
@@ -1,5 +1,10 @@
+pub mod check;
+pub mod config;
 pub mod constants;
+pub mod health;
 pub mod logging;
 pub mod mqtt;
 pub mod payload;
+pub mod shutdown;
 pub mod usage;
+pub mod web;
@@ -1,15 +1,685 @@
-pub fn init(level: log::LevelFilter) -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
-        .format(|out, msg, record| {
-            out.finish(format_args!(
-                "{:<6}: {} {}",
-                record.level(),
-                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%z"),
-                msg
-            ))
-        })
-        .level(level)
-        .chain(std::io::stdout())
-        .apply()?;
+use crate::constants;
+
+use serde::Deserialize;
+use simple_error::bail;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixDatagram;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Logging {
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default)]
+    pub level: String,
+    #[serde(default)]
+    pub levels: HashMap<String, String>,
+    #[serde(default)]
+    pub output: Output,
+    #[serde(default = "logging_default_syslog_facility")]
+    pub syslog_facility: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Output {
+    #[default]
+    Stdout,
+    File,
+    Syslog,
+    Journald,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            file: String::new(),
+            format: Format::Text,
+            level: String::new(),
+            levels: HashMap::new(),
+            output: Output::Stdout,
+            syslog_facility: logging_default_syslog_facility(),
+        }
+    }
+}
+
+fn logging_default_syslog_facility() -> String {
+    constants::DEFAULT_SYSLOG_FACILITY.to_string()
+}
+
+enum Sink {
+    Stdout,
+    File { path: String, file: Mutex<File> },
+    Syslog(Mutex<syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>>),
+    Journald(UnixDatagram),
+}
+
+struct Logger {
+    format: Format,
+    level: log::LevelFilter,
+    // (module, level), longest module first so the most specific setting wins
+    levels: Vec<(String, log::LevelFilter)>,
+    sink: Sink,
+}
+
+// log only allows to set the logger once, the real logger is replaced after the configuration has been parsed
+struct Proxy;
+
+static PROXY: Proxy = Proxy;
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+impl log::Log for Proxy {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        match LOGGER.read() {
+            Ok(v) => match v.as_ref() {
+                Some(l) => metadata.level() <= l.level_for(metadata.target()),
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Ok(v) = LOGGER.read() {
+            if let Some(l) = v.as_ref() {
+                if record.level() <= l.level_for(record.target()) {
+                    l.write(record);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> log::LevelFilter {
+        for (module, level) in self.levels.iter() {
+            if target == module
+                || (target.starts_with(module.as_str()) && target[module.len()..].starts_with("::"))
+            {
+                return *level;
+            }
+        }
+        self.level
+    }
+
+    fn write(&self, record: &log::Record) {
+        let result = match &self.sink {
+            Sink::Stdout => writeln!(std::io::stdout(), "{}", self.format_line(record, true)),
+            Sink::File { file, .. } => match file.lock() {
+                Ok(mut f) => writeln!(f, "{}", self.format_line(record, true)),
+                Err(_) => return,
+            },
+            Sink::Syslog(logger) => {
+                let line = self.format_line(record, false);
+                match logger.lock() {
+                    Ok(mut l) => match record.level() {
+                        log::Level::Error => l.err(line),
+                        log::Level::Warn => l.warning(line),
+                        log::Level::Info => l.info(line),
+                        log::Level::Debug | log::Level::Trace => l.debug(line),
+                    }
+                    .map_err(|e| std::io::Error::other(e.to_string())),
+                    Err(_) => return,
+                }
+            }
+            Sink::Journald(sock) => journald_send(
+                sock,
+                &journald_entry(record, &self.format_line(record, false)),
+            ),
+        };
+
+        if let Err(e) = result {
+            // nowhere else to report it
+            eprintln!("can't write log message - {}: {}", e, record.args());
+        }
+    }
+
+    fn format_line(&self, record: &log::Record, with_time: bool) -> String {
+        match self.format {
+            Format::Text => {
+                if with_time {
+                    format!(
+                        "{:<6}: {} {}",
+                        record.level(),
+                        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%z"),
+                        record.args()
+                    )
+                } else {
+                    record.args().to_string()
+                }
+            }
+            Format::Json => {
+                let mut entry = serde_json::Map::new();
+                if with_time {
+                    entry.insert(
+                        "time".to_string(),
+                        chrono::Local::now()
+                            .format("%Y-%m-%dT%H:%M:%S%.3f%z")
+                            .to_string()
+                            .into(),
+                    );
+                }
+                entry.insert("level".to_string(), record.level().as_str().into());
+                entry.insert("target".to_string(), record.target().into());
+                entry.insert("message".to_string(), record.args().to_string().into());
+                for (k, v) in fields(record) {
+                    entry.insert(k, v);
+                }
+                serde_json::Value::Object(entry).to_string()
+            }
+        }
+    }
+}
+
+struct FieldVisitor(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for FieldVisitor {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let v: serde_json::Value = if let Some(v) = value.to_u64() {
+            v.into()
+        } else if let Some(v) = value.to_i64() {
+            v.into()
+        } else if let Some(v) = value.to_f64() {
+            v.into()
+        } else if let Some(v) = value.to_bool() {
+            v.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.push((key.as_str().to_string(), v));
+        Ok(())
+    }
+}
+
+// structured fields, e.g. info!(scrape = name; "...")
+fn fields(record: &log::Record) -> Vec<(String, serde_json::Value)> {
+    let mut visitor = FieldVisitor(Vec::new());
+    let _ = record.key_values().visit(&mut visitor);
+    visitor.0
+}
+
+// Native journald protocol, see https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+fn journald_entry(record: &log::Record, message: &str) -> Vec<u8> {
+    let priority = match record.level() {
+        log::Level::Error => "3",
+        log::Level::Warn => "4",
+        log::Level::Info => "6",
+        log::Level::Debug | log::Level::Trace => "7",
+    };
+    let mut entry: Vec<u8> = Vec::new();
+
+    journald_field(&mut entry, "MESSAGE", message);
+    journald_field(&mut entry, "PRIORITY", priority);
+    journald_field(&mut entry, "SYSLOG_IDENTIFIER", &process_name());
+    journald_field(&mut entry, "TARGET", record.target());
+    for (k, v) in fields(record) {
+        // field names must only consist of upper case letters, digits and underscores
+        let name: String = k
+            .to_uppercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let value = match v {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        journald_field(&mut entry, name.trim_start_matches('_'), &value);
+    }
+    entry
+}
+
+fn journald_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+fn journald_send(sock: &UnixDatagram, entry: &[u8]) -> io::Result<()> {
+    match sock.send(entry) {
+        Ok(_) => Ok(()),
+        // too large for a single datagram, journald accepts the entry in a sealed memfd instead
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EMSGSIZE) | Some(libc::ENOBUFS)) => {
+            journald_send_memfd(sock, entry)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn journald_send_memfd(sock: &UnixDatagram, entry: &[u8]) -> io::Result<()> {
+    let name = CString::new(constants::PACKAGE_NAME)?;
+    // SAFETY: name is a valid NUL terminated string
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a newly created file descriptor owned by nothing else, it is closed on drop
+    let mut memfd = unsafe { File::from_raw_fd(fd) };
+    memfd.write_all(entry)?;
+
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    // SAFETY: fd is valid as long as memfd hasn't been dropped
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    send_fd(sock, fd)
+}
+
+// Send fd as SCM_RIGHTS ancillary data of an empty datagram
+fn send_fd(sock: &UnixDatagram, fd: libc::c_int) -> io::Result<()> {
+    let fd_size = std::mem::size_of::<libc::c_int>() as libc::c_uint;
+    // SAFETY: CMSG_SPACE only calculates the size
+    let space = unsafe { libc::CMSG_SPACE(fd_size) } as usize;
+    // u64 keeps the buffer aligned for cmsghdr
+    let mut control = vec![0u64; space.div_ceil(8)];
+
+    // SAFETY: msghdr is a plain C structure, all fields are valid when zeroed
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+
+    // SAFETY: msg_control points to a buffer large enough for one cmsghdr carrying a single fd
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::c_int, fd);
+    }
+
+    // SAFETY: the socket is connected and msg only references buffers living until the call returns
+    if unsafe { libc::sendmsg(sock.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
     Ok(())
 }
+
+fn process_name() -> String {
+    match std::env::current_exe() {
+        Ok(v) => match v.file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => constants::PACKAGE_NAME.to_string(),
+        },
+        Err(_) => constants::PACKAGE_NAME.to_string(),
+    }
+}
+
+fn open_log_file(path: &str) -> Result<File, Box<dyn Error>> {
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("can't open log file {} - {}", path, e),
+    }
+}
+
+fn parse_level(s: &str) -> Result<log::LevelFilter, Box<dyn Error>> {
+    match log::LevelFilter::from_str(s) {
+        Ok(v) => Ok(v),
+        Err(_) => bail!("invalid log level {}", s),
+    }
+}
+
+// Module names without a path refer to the modules of the binary, e.g. scrape for prom2mqtt_fetch::scrape
+fn module_path(crate_name: &str, module: &str) -> String {
+    if module.contains("::") || module == crate_name || module == "global" {
+        module.to_string()
+    } else {
+        format!("{}::{}", crate_name, module)
+    }
+}
+
+// Parse <module>=<level> from the command line
+pub fn parse_module_level(s: &str) -> Result<(String, String), Box<dyn Error>> {
+    match s.split_once('=') {
+        Some((m, l)) if !m.is_empty() => {
+            parse_level(l)?;
+            Ok((m.to_string(), l.to_string()))
+        }
+        _ => bail!("invalid module log level {}, expected <module>=<level>", s),
+    }
+}
+
+pub fn validate(cfg: &Logging) -> Result<(), Box<dyn Error>> {
+    if !cfg.level.is_empty() {
+        parse_level(&cfg.level)?;
+    }
+    for (module, level) in cfg.levels.iter() {
+        if module.is_empty() {
+            bail!("empty module name for log level");
+        }
+        parse_level(level)?;
+    }
+    if cfg.output == Output::File && cfg.file.is_empty() {
+        bail!("log output is set to file, but no file is configured");
+    }
+    if cfg.output == Output::Syslog && syslog::Facility::from_str(&cfg.syslog_facility).is_err() {
+        bail!("invalid syslog facility {}", cfg.syslog_facility);
+    }
+    Ok(())
+}
+
+// Start logging to stdout, used until the configuration file has been parsed
+pub fn init(level: log::LevelFilter) -> Result<(), Box<dyn Error>> {
+    replace(Logger {
+        format: Format::Text,
+        level,
+        levels: Vec::new(),
+        sink: Sink::Stdout,
+    });
+    log::set_logger(&PROXY)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// Switch to the configured logging. Levels from the command line take precedence over the configuration file
+pub fn configure(
+    cfg: &Logging,
+    crate_name: &str,
+    cli_level: Option<log::LevelFilter>,
+    cli_levels: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    let level = match cli_level {
+        Some(v) => v,
+        None if !cfg.level.is_empty() => parse_level(&cfg.level)?,
+        None => log::LevelFilter::Info,
+    };
+
+    let mut module_levels: HashMap<String, log::LevelFilter> = HashMap::new();
+    for (m, l) in cfg
+        .levels
+        .iter()
+        .chain(cli_levels.iter().map(|(m, l)| (m, l)))
+    {
+        module_levels.insert(module_path(crate_name, m), parse_level(l)?);
+    }
+    let mut levels: Vec<(String, log::LevelFilter)> = module_levels.into_iter().collect();
+    levels.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+
+    let sink = match cfg.output {
+        Output::Stdout => Sink::Stdout,
+        Output::File => Sink::File {
+            path: cfg.file.clone(),
+            file: Mutex::new(open_log_file(&cfg.file)?),
+        },
+        Output::Syslog => {
+            let facility = match syslog::Facility::from_str(&cfg.syslog_facility) {
+                Ok(v) => v,
+                Err(_) => bail!("invalid syslog facility {}", cfg.syslog_facility),
+            };
+            let formatter = syslog::Formatter3164 {
+                facility,
+                hostname: None,
+                process: process_name(),
+                pid: std::process::id(),
+            };
+            match syslog::unix(formatter) {
+                Ok(v) => Sink::Syslog(Mutex::new(v)),
+                Err(e) => bail!("can't connect to syslog - {}", e),
+            }
+        }
+        Output::Journald => {
+            let sock = UnixDatagram::unbound()?;
+            if let Err(e) = sock.connect(constants::JOURNALD_SOCKET) {
+                bail!(
+                    "can't connect to journald socket {} - {}",
+                    constants::JOURNALD_SOCKET,
+                    e
+                );
+            }
+            Sink::Journald(sock)
+        }
+    };
+
+    let max_level = levels.iter().map(|(_, l)| *l).fold(level, std::cmp::max);
+    replace(Logger {
+        format: cfg.format.clone(),
+        level,
+        levels,
+        sink,
+    });
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+// Reopen the log file, e.g. after it has been rotated
+pub fn reopen() -> Result<(), Box<dyn Error>> {
+    if let Ok(v) = LOGGER.read() {
+        if let Some(Logger {
+            sink: Sink::File { path, file },
+            ..
+        }) = v.as_ref()
+        {
+            let new = open_log_file(path)?;
+            if let Ok(mut f) = file.lock() {
+                *f = new;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn replace(logger: Logger) {
+    if let Ok(mut v) = LOGGER.write() {
+        *v = Some(logger);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek};
+
+    fn logger(format: Format, levels: &[(&str, log::LevelFilter)]) -> Logger {
+        Logger {
+            format,
+            level: log::LevelFilter::Info,
+            levels: levels.iter().map(|(m, l)| (m.to_string(), *l)).collect(),
+            sink: Sink::Stdout,
+        }
+    }
+
+    #[test]
+    fn module_levels_match_path_prefixes() {
+        let l = logger(
+            Format::Text,
+            &[
+                ("prom2mqtt_fetch::scrape", log::LevelFilter::Debug),
+                ("prom2mqtt_fetch", log::LevelFilter::Warn),
+            ],
+        );
+        assert_eq!(
+            l.level_for("prom2mqtt_fetch::scrape"),
+            log::LevelFilter::Debug
+        );
+        assert_eq!(
+            l.level_for("prom2mqtt_fetch::scrape::inner"),
+            log::LevelFilter::Debug
+        );
+        // prefix of the name but not of the module path
+        assert_eq!(
+            l.level_for("prom2mqtt_fetch::scraper"),
+            log::LevelFilter::Warn
+        );
+        assert_eq!(l.level_for("prom2mqtt_fetch"), log::LevelFilter::Warn);
+        assert_eq!(l.level_for("prom2mqtt_fetcher"), log::LevelFilter::Info);
+        assert_eq!(l.level_for("paho_mqtt::client"), log::LevelFilter::Info);
+    }
+
+    #[test]
+    fn module_names_are_relative_to_the_binary() {
+        assert_eq!(
+            module_path("prom2mqtt_fetch", "scrape"),
+            "prom2mqtt_fetch::scrape".to_string()
+        );
+        assert_eq!(
+            module_path("prom2mqtt_fetch", "paho_mqtt::client"),
+            "paho_mqtt::client".to_string()
+        );
+        assert_eq!(
+            module_path("prom2mqtt_fetch", "prom2mqtt_fetch"),
+            "prom2mqtt_fetch".to_string()
+        );
+        assert_eq!(
+            module_path("prom2mqtt_fetch", "global"),
+            "global".to_string()
+        );
+    }
+
+    #[test]
+    fn module_levels_from_the_command_line() {
+        assert_eq!(
+            parse_module_level("scrape=debug").unwrap(),
+            ("scrape".to_string(), "debug".to_string())
+        );
+        assert_eq!(
+            parse_module_level("paho_mqtt=OFF").unwrap(),
+            ("paho_mqtt".to_string(), "OFF".to_string())
+        );
+        assert!(parse_module_level("scrape").is_err());
+        assert!(parse_module_level("=debug").is_err());
+        assert!(parse_module_level("scrape=verbose").is_err());
+        assert!(parse_module_level("scrape=").is_err());
+    }
+
+    #[test]
+    fn journald_fields() {
+        let mut entry = Vec::new();
+        journald_field(&mut entry, "MESSAGE", "single line");
+        assert_eq!(entry, b"MESSAGE=single line\n".to_vec());
+
+        // values containing a newline are sent with their length as 64 bit little endian
+        let mut entry = Vec::new();
+        journald_field(&mut entry, "MESSAGE", "two\nlines");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&[9, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"two\nlines\n");
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn journald_entry_with_fields() {
+        let kvs = [
+            ("scrape", log::kv::Value::from("node")),
+            ("http.status", log::kv::Value::from(503u64)),
+        ];
+        let entry = journald_entry(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("prom2mqtt_fetch::scrape")
+                .key_values(&kvs)
+                .args(format_args!("failed"))
+                .build(),
+            "failed",
+        );
+        let entry = String::from_utf8(entry).unwrap();
+        assert!(entry.starts_with("MESSAGE=failed\nPRIORITY=4\n"));
+        assert!(entry.contains("\nTARGET=prom2mqtt_fetch::scrape\n"));
+        assert!(entry.contains("\nSCRAPE=node\n"));
+        assert!(entry.ends_with("\nHTTP_STATUS=503\n"));
+    }
+
+    #[test]
+    fn large_journald_entries_are_sent_as_memfd() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        let entry = vec![b'x'; 4 * 1024 * 1024];
+        journald_send(&sender, &entry).unwrap();
+
+        let mut data = [0u8; 16];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut control = [0u64; 8];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let n = unsafe { libc::recvmsg(receiver.as_raw_fd(), &mut msg, 0) };
+        assert_eq!(n, 0);
+        let fd = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+            std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int)
+        };
+
+        let mut memfd = unsafe { File::from_raw_fd(fd) };
+        memfd.rewind().unwrap();
+        let mut received = Vec::new();
+        memfd.read_to_end(&mut received).unwrap();
+        assert_eq!(received, entry);
+        // sealed, journald relies on the content not changing
+        assert!(memfd.write_all(b"x").is_err());
+    }
+
+    #[test]
+    fn json_format() {
+        let kvs = [
+            ("scrape", log::kv::Value::from("node")),
+            ("bytes", log::kv::Value::from(42u64)),
+            ("duration", log::kv::Value::from(0.5f64)),
+        ];
+        let line = logger(Format::Json, &[]).format_line(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .target("prom2mqtt_fetch::scrape")
+                .key_values(&kvs)
+                .args(format_args!("scraped \"{}\"", "node"))
+                .build(),
+            false,
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!({
+                "level": "INFO",
+                "target": "prom2mqtt_fetch::scrape",
+                "message": "scraped \"node\"",
+                "scrape": "node",
+                "bytes": 42,
+                "duration": 0.5,
+            })
+        );
+
+        let line = logger(Format::Json, &[]).format_line(
+            &log::Record::builder()
+                .level(log::Level::Error)
+                .args(format_args!("failed"))
+                .build(),
+            true,
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert!(parsed["time"].is_string());
+        assert_eq!(parsed["level"], "ERROR");
+    }
+}
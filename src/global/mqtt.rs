@@ -1,8 +1,10 @@
+use crate::config;
 use crate::constants;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::Deserialize;
 use std::error::Error;
+use std::sync::OnceLock;
 use std::time::Duration;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -16,7 +18,10 @@ pub struct MQTT {
     pub client_id: String,
     #[serde(default)]
     pub insecure_ssl: bool,
-    pub password: String,
+    #[serde(default)]
+    pub password: config::Secret,
+    #[serde(default)]
+    pub password_file: String,
     #[serde(default)]
     pub qos: i32,
     #[serde(default = "mqtt_default_reconnect_timeout")]
@@ -35,7 +40,13 @@ fn mqtt_default_reconnect_timeout() -> u64 {
     constants::DEFAULT_MQTT_RECONNECT_TIMEOUT
 }
 
+// The generated client ID must not change if the configuration is parsed again on reload
 fn mqtt_default_client_id() -> String {
+    static CLIENT_ID: OnceLock<String> = OnceLock::new();
+    CLIENT_ID.get_or_init(random_client_id).clone()
+}
+
+pub fn random_client_id() -> String {
     thread_rng()
         .sample_iter(&Alphanumeric)
         .take(constants::MAXIMAL_CLIENT_ID_LENGTH)
@@ -43,6 +54,23 @@ fn mqtt_default_client_id() -> String {
         .collect()
 }
 
+// Topic, QoS and reconnect_timeout can be changed without a new connection to the broker
+pub fn connection_changed(old: &MQTT, new: &MQTT) -> bool {
+    old.broker != new.broker
+        || old.ca_cert != new.ca_cert
+        || old.clean_session != new.clean_session
+        || old.client_id != new.client_id
+        || old.insecure_ssl != new.insecure_ssl
+        || old.password != new.password
+        || old.timeout != new.timeout
+        || old.user != new.user
+}
+
+// Read secrets stored in separate files
+pub fn load_secrets(cfg: &mut MQTT) -> Result<(), Box<dyn Error>> {
+    config::secret_from_file(&mut cfg.password, &cfg.password_file, "password")
+}
+
 pub fn connection_builder(
     cfg: &MQTT,
 ) -> Result<paho_mqtt::connect_options::ConnectOptions, Box<dyn Error>> {
@@ -65,7 +93,7 @@ pub fn connection_builder(
         .clean_session(cfg.clean_session)
         .connect_timeout(Duration::from_secs(cfg.timeout))
         .user_name(&cfg.user)
-        .password(&cfg.password)
+        .password(cfg.password.expose())
         .retry_interval(Duration::from_secs(1))
         .ssl_options(sslopts.finalize())
         .finalize();
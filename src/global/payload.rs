@@ -1,14 +1,17 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Message {
     #[serde(default)]
     pub expiration: i64,
+    // marks the message of a failed scrape, the receiver drops the data of the source
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
     pub name: String,
     pub payload: Vec<Payload>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Payload {
     pub data: Vec<String>,
     #[serde(alias = "type")]
@@ -27,6 +30,7 @@ impl Message {
     pub fn new() -> Self {
         Message {
             expiration: 0,
+            failed: false,
             name: String::new(),
             payload: Vec::<Payload>::new(),
         }
@@ -0,0 +1,52 @@
+use log::{debug, error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{process, thread};
+
+// Set the stop flag on SIGINT/SIGTERM, a second signal terminates immediately
+pub fn handle_signals(stop: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for sig in signals.forever() {
+            if stop.swap(true, Ordering::SeqCst) {
+                warn!("signal {} received again, terminating immediately", sig);
+                process::exit(1);
+            }
+            info!("signal {} received, shutting down", sig);
+        }
+    });
+    Ok(())
+}
+
+// Box<dyn Error> can't be passed between threads. A failing worker thread also stops
+// all other threads, so the process can terminate
+pub fn worker_result(stop: &AtomicBool, result: Result<(), Box<dyn Error>>) -> Result<(), String> {
+    if result.is_err() {
+        stop.store(true, Ordering::SeqCst);
+    }
+    result.map_err(|e| e.to_string())
+}
+
+pub fn join(name: &str, handle: thread::JoinHandle<Result<(), String>>) -> bool {
+    match handle.join() {
+        Ok(Ok(_)) => {
+            debug!("{} thread stopped", name);
+            true
+        }
+        Ok(Err(e)) => {
+            error!("{} thread failed: {}", name, e);
+            false
+        }
+        Err(_) => {
+            error!("{} thread panicked", name);
+            false
+        }
+    }
+}
+
+pub fn requested(stop: &AtomicBool) -> bool {
+    stop.load(Ordering::SeqCst)
+}
@@ -0,0 +1,694 @@
+use crate::config;
+use crate::constants;
+use base64::Engine;
+use log::{debug, error, info};
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use simple_error::bail;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Deref;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Web {
+    #[serde(default)]
+    pub basic_auth_users: HashMap<String, config::Secret>,
+    pub tls_server_config: Option<TlsServerConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TlsServerConfig {
+    pub cert_file: String,
+    #[serde(default = "web_default_client_auth_type")]
+    pub client_auth_type: String,
+    #[serde(default)]
+    pub client_ca_file: String,
+    pub key_file: String,
+}
+
+fn web_default_client_auth_type() -> String {
+    constants::WEB_CLIENT_AUTH_NONE.to_string()
+}
+
+pub struct Authenticator {
+    users: HashMap<String, config::Secret>,
+    // bcrypt is slow by design, remember the result for Authorization headers already seen
+    cache: HashMap<String, bool>,
+}
+
+impl Authenticator {
+    pub fn new(cfg: &Web) -> Self {
+        Authenticator {
+            users: cfg.basic_auth_users.clone(),
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn authorized(&mut self, request: &tiny_http::Request) -> bool {
+        let header = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str());
+        self.authorized_header(header)
+    }
+
+    fn authorized_header(&mut self, header: Option<&str>) -> bool {
+        if self.users.is_empty() {
+            return true;
+        }
+
+        let header = match header {
+            Some(v) => v.to_string(),
+            None => return false,
+        };
+
+        if let Some(v) = self.cache.get(&header) {
+            return *v;
+        }
+
+        let result = self.check(&header);
+        if self.cache.len() >= constants::WEB_AUTH_CACHE_SIZE {
+            self.cache.clear();
+        }
+        self.cache.insert(header, result);
+        result
+    }
+
+    fn check(&self, header: &str) -> bool {
+        let encoded = match header.strip_prefix("Basic ") {
+            Some(v) => v.trim(),
+            None => return false,
+        };
+        let decoded = match base64::engine::general_purpose::STANDARD.decode(encoded) {
+            Ok(v) => v,
+            Err(e) => {
+                debug!("can't decode basic auth credentials - {}", e);
+                return false;
+            }
+        };
+        let credentials = String::from_utf8_lossy(&decoded);
+        let (user, password) = match credentials.split_once(':') {
+            Some(v) => v,
+            None => return false,
+        };
+
+        match self.users.get(user) {
+            Some(hash) => match bcrypt::verify(password, hash.expose()) {
+                Ok(v) => {
+                    if !v {
+                        info!("invalid password for user {}", user);
+                    }
+                    v
+                }
+                Err(e) => {
+                    error!("can't verify password of user {} - {}", user, e);
+                    false
+                }
+            },
+            None => {
+                info!("unknown user {}", user);
+                false
+            }
+        }
+    }
+}
+
+pub fn unauthorized_header() -> tiny_http::Header {
+    tiny_http::Header::from_bytes(
+        &b"WWW-Authenticate"[..],
+        format!("Basic realm=\"{}\"", constants::PACKAGE_NAME).as_bytes(),
+    )
+    .unwrap()
+}
+
+pub fn scheme(cfg: &Web) -> &str {
+    if cfg.tls_server_config.is_some() {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+pub fn validate(cfg: &Web) -> Result<(), Box<dyn Error>> {
+    for (user, hash) in cfg.basic_auth_users.iter() {
+        if user.is_empty() || user.contains(':') {
+            bail!("invalid user name '{}' for basic auth", user);
+        }
+        if let Err(e) = hash.expose().parse::<bcrypt::HashParts>() {
+            bail!("invalid bcrypt hash for user {} - {}", user, e);
+        }
+    }
+
+    if let Some(tls) = &cfg.tls_server_config {
+        if tls.cert_file.is_empty() {
+            bail!("no server certificate configured");
+        }
+        if tls.key_file.is_empty() {
+            bail!("no server key configured");
+        }
+        match tls.client_auth_type.as_str() {
+            constants::WEB_CLIENT_AUTH_NONE => {}
+            constants::WEB_CLIENT_AUTH_VERIFY_IF_GIVEN
+            | constants::WEB_CLIENT_AUTH_REQUIRE_AND_VERIFY => {
+                if tls.client_ca_file.is_empty() {
+                    bail!(
+                        "client_auth_type {} requires a client_ca_file",
+                        tls.client_auth_type
+                    );
+                }
+            }
+            _ => bail!("invalid client_auth_type {}", tls.client_auth_type),
+        };
+    }
+    Ok(())
+}
+
+// HTTP listener. With TLS, the private directory holding the socket of tiny_http is removed when
+// the server is dropped
+pub struct Server {
+    inner: tiny_http::Server,
+    private_dir: Option<PathBuf>,
+}
+
+impl Deref for Server {
+    type Target = tiny_http::Server;
+
+    fn deref(&self) -> &tiny_http::Server {
+        &self.inner
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // only remove what has been created by server()
+        if let Some(dir) = &self.private_dir {
+            let _ = fs::remove_file(dir.join(constants::WEB_TLS_BACKEND_SOCKET));
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+pub fn server(listen: &str, cfg: &Web) -> Result<Server, Box<dyn Error>> {
+    let tls = match &cfg.tls_server_config {
+        Some(v) => v,
+        None => {
+            return match tiny_http::Server::http(listen) {
+                Ok(v) => Ok(Server {
+                    inner: v,
+                    private_dir: None,
+                }),
+                Err(e) => bail!("cant start HTTP server - {}", e),
+            }
+        }
+    };
+
+    // The TLS support of tiny_http can't verify client certificates. TLS is terminated here and the
+    // decrypted connection is passed to tiny_http listening on a UNIX socket. The socket is created
+    // in a new directory only accessible by the current user, so no other user can bypass the TLS
+    // frontend, not even while the socket is being set up
+    let acceptor = Arc::new(build_acceptor(tls)?);
+    let private_dir = private_dir()?;
+    let backend = private_dir.join(constants::WEB_TLS_BACKEND_SOCKET);
+    let server = match tiny_http::Server::http_unix(&backend) {
+        Ok(v) => Server {
+            inner: v,
+            private_dir: Some(private_dir),
+        },
+        Err(e) => {
+            let _ = fs::remove_dir(&private_dir);
+            bail!("cant start HTTP server - {}", e);
+        }
+    };
+
+    let listener = TcpListener::bind(listen)?;
+    debug!(
+        "terminating TLS on {} and forwarding to {}",
+        listen,
+        backend.display()
+    );
+    thread::spawn(move || tls_frontend(listener, acceptor, backend));
+
+    Ok(server)
+}
+
+// Like mkdtemp(3): the directory is created with a random name and mode 0700, creating it fails
+// if anything already exists at that path
+fn private_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let base = std::env::temp_dir();
+    for _ in 0..constants::WEB_PRIVATE_DIR_ATTEMPTS {
+        let suffix: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect();
+        let dir = base.join(format!("{}-{}", constants::PACKAGE_NAME, suffix));
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(_) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => bail!("can't create directory {} - {}", dir.display(), e),
+        };
+    }
+    bail!("can't create a private directory in {}", base.display());
+}
+
+fn build_acceptor(cfg: &TlsServerConfig) -> Result<SslAcceptor, Box<dyn Error>> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    builder.set_certificate_chain_file(&cfg.cert_file)?;
+    builder.set_private_key_file(&cfg.key_file, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+
+    match cfg.client_auth_type.as_str() {
+        constants::WEB_CLIENT_AUTH_VERIFY_IF_GIVEN => {
+            builder.set_ca_file(&cfg.client_ca_file)?;
+            builder.set_verify(SslVerifyMode::PEER);
+        }
+        constants::WEB_CLIENT_AUTH_REQUIRE_AND_VERIFY => {
+            builder.set_ca_file(&cfg.client_ca_file)?;
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+        _ => builder.set_verify(SslVerifyMode::NONE),
+    };
+
+    Ok(builder.build())
+}
+
+fn tls_frontend(listener: TcpListener, acceptor: Arc<SslAcceptor>, backend: PathBuf) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(v) => {
+                let acceptor = acceptor.clone();
+                let backend = backend.clone();
+                thread::spawn(move || {
+                    // tiny_http only sees the UNIX socket, so the address of the client is logged here
+                    let peer = v
+                        .peer_addr()
+                        .map(|a| a.to_string())
+                        .unwrap_or_else(|_| "unknown address".to_string());
+                    debug!("TLS connection from {}", peer);
+                    if let Err(e) = tls_connection(v, &acceptor, &backend) {
+                        debug!("TLS connection from {} failed - {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("can't accept incoming connection - {}", e);
+            }
+        };
+    }
+}
+
+fn tls_connection(
+    stream: TcpStream,
+    acceptor: &SslAcceptor,
+    backend: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut buffer = [0u8; 16384];
+
+    let handshake_timeout = Some(Duration::from_secs(constants::WEB_TLS_HANDSHAKE_TIMEOUT));
+    stream.set_read_timeout(handshake_timeout)?;
+    stream.set_write_timeout(handshake_timeout)?;
+    let mut tls = acceptor.accept(stream)?;
+    tls.get_ref().set_read_timeout(None)?;
+    tls.get_ref().set_write_timeout(None)?;
+    let mut plain = UnixStream::connect(backend)?;
+
+    // SslStream can't be split, so block until one of both sides is readable. Data already
+    // decrypted by OpenSSL doesn't show up in poll(2)
+    loop {
+        let (from_client, from_backend) = if tls.ssl().pending() > 0 {
+            (true, false)
+        } else {
+            wait_readable(tls.get_ref().as_raw_fd(), plain.as_raw_fd())?
+        };
+
+        if from_client {
+            match tls.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => plain.write_all(&buffer[..n])?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Box::new(e)),
+            };
+        }
+        if from_backend {
+            match plain.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => tls.write_all(&buffer[..n])?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Box::new(e)),
+            };
+        }
+    }
+
+    let _ = tls.shutdown();
+    Ok(())
+}
+
+// Wait until one of both file descriptors is readable (or closed), returns the readable ones
+fn wait_readable(first: RawFd, second: RawFd) -> io::Result<(bool, bool)> {
+    let mut fds = [
+        libc::pollfd {
+            fd: first,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: second,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        // SAFETY: fds is a valid array of two pollfd structures for the duration of the call
+        let rc = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if rc < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        let ready =
+            |p: &libc::pollfd| p.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0;
+        return Ok((ready(&fds[0]), ready(&fds[1])));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::{BigNum, MsbOption};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslConnector, SslStream};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    fn basic(user: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password))
+        )
+    }
+
+    fn authenticator() -> Authenticator {
+        let mut cfg = Web::default();
+        // lowest cost to keep the tests fast
+        cfg.basic_auth_users.insert(
+            "prometheus".to_string(),
+            bcrypt::hash("S3cr37", 4).unwrap().into(),
+        );
+        Authenticator::new(&cfg)
+    }
+
+    #[test]
+    fn everything_is_authorized_without_users() {
+        let mut auth = Authenticator::new(&Web::default());
+        assert!(auth.authorized_header(None));
+        assert!(auth.authorized_header(Some("Basic invalid")));
+    }
+
+    #[test]
+    fn basic_auth() {
+        let mut auth = authenticator();
+        assert!(auth.authorized_header(Some(&basic("prometheus", "S3cr37"))));
+        assert!(!auth.authorized_header(None));
+        assert!(!auth.authorized_header(Some(&basic("prometheus", "wrong"))));
+        assert!(!auth.authorized_header(Some(&basic("unknown", "S3cr37"))));
+        assert!(!auth.authorized_header(Some("Bearer S3cr37")));
+        assert!(!auth.authorized_header(Some("Basic !!!")));
+        // no separator between user and password
+        let encoded = base64::engine::general_purpose::STANDARD.encode("prometheus");
+        assert!(!auth.authorized_header(Some(&format!("Basic {}", encoded))));
+    }
+
+    #[test]
+    fn basic_auth_results_are_cached() {
+        let mut auth = authenticator();
+        let good = basic("prometheus", "S3cr37");
+        let bad = basic("prometheus", "wrong");
+        assert!(auth.authorized_header(Some(&good)));
+        assert!(!auth.authorized_header(Some(&bad)));
+        assert_eq!(auth.cache.get(&good), Some(&true));
+        assert_eq!(auth.cache.get(&bad), Some(&false));
+        assert!(auth.authorized_header(Some(&good)));
+    }
+
+    #[test]
+    fn validate_users_and_tls() {
+        let mut cfg = authenticator_config("prometheus", "$2b$04$invalid");
+        assert!(validate(&cfg).is_err());
+
+        cfg = authenticator_config("prom:etheus", &bcrypt::hash("x", 4).unwrap());
+        assert!(validate(&cfg).is_err());
+
+        cfg = authenticator_config("prometheus", &bcrypt::hash("x", 4).unwrap());
+        assert!(validate(&cfg).is_ok());
+
+        let mut tls = TlsServerConfig {
+            cert_file: "server.crt".to_string(),
+            client_auth_type: constants::WEB_CLIENT_AUTH_REQUIRE_AND_VERIFY.to_string(),
+            client_ca_file: String::new(),
+            key_file: "server.key".to_string(),
+        };
+        cfg.tls_server_config = Some(tls.clone());
+        assert!(validate(&cfg).is_err());
+
+        tls.client_ca_file = "ca.crt".to_string();
+        cfg.tls_server_config = Some(tls.clone());
+        assert!(validate(&cfg).is_ok());
+
+        tls.client_auth_type = "RequireAnyClientCert".to_string();
+        cfg.tls_server_config = Some(tls);
+        assert!(validate(&cfg).is_err());
+    }
+
+    fn authenticator_config(user: &str, hash: &str) -> Web {
+        let mut cfg = Web::default();
+        cfg.basic_auth_users
+            .insert(user.to_string(), hash.to_string().into());
+        cfg
+    }
+
+    struct Pki {
+        dir: PathBuf,
+        ca: (X509, PKey<Private>),
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "{}-test-{}-{}",
+                constants::PACKAGE_NAME,
+                name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            let ca = certificate("Test CA", None, true);
+            Pki { dir, ca }
+        }
+
+        // write a certificate signed by the CA of this PKI and its key, returns the file names
+        fn issue(&self, cn: &str) -> (String, String) {
+            let (cert, key) = certificate(cn, Some(&self.ca), false);
+            self.write(cn, &cert, &key)
+        }
+
+        fn write(&self, name: &str, cert: &X509, key: &PKey<Private>) -> (String, String) {
+            let cert_file = self.dir.join(format!("{}.crt", name));
+            let key_file = self.dir.join(format!("{}.key", name));
+            fs::write(&cert_file, cert.to_pem().unwrap()).unwrap();
+            fs::write(&key_file, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+            (
+                cert_file.to_string_lossy().to_string(),
+                key_file.to_string_lossy().to_string(),
+            )
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn certificate(
+        cn: &str,
+        issuer: Option<&(X509, PKey<Private>)>,
+        ca: bool,
+    ) -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+        builder
+            .set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        if ca {
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+        } else {
+            let san = SubjectAlternativeName::new()
+                .dns(cn)
+                .build(&builder.x509v3_context(issuer.map(|i| i.0.as_ref()), None))
+                .unwrap();
+            builder.append_extension(san).unwrap();
+        }
+        match issuer {
+            Some((cert, ikey)) => {
+                builder.set_issuer_name(cert.subject_name()).unwrap();
+                builder.sign(ikey, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                builder.set_issuer_name(&name).unwrap();
+                builder.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        };
+        (builder.build(), key)
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn tls_server(pki: &Pki, client_auth_type: &str) -> (Server, u16) {
+        let (cert_file, key_file) = pki.issue("localhost");
+        let (ca_file, _) = pki.write("ca", &pki.ca.0, &pki.ca.1);
+        let cfg = Web {
+            basic_auth_users: HashMap::new(),
+            tls_server_config: Some(TlsServerConfig {
+                cert_file,
+                client_auth_type: client_auth_type.to_string(),
+                client_ca_file: ca_file,
+                key_file,
+            }),
+        };
+        let port = free_port();
+        (server(&format!("127.0.0.1:{}", port), &cfg).unwrap(), port)
+    }
+
+    // Send a request using an optional client certificate, returns the reply or None if the
+    // TLS handshake or the request failed
+    fn request(
+        pki: &Pki,
+        port: u16,
+        server: &Server,
+        client: Option<(&str, &str)>,
+    ) -> Option<String> {
+        let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+        connector
+            .set_ca_file(pki.dir.join("ca.crt").to_string_lossy().to_string())
+            .unwrap();
+        if let Some((cert, key)) = client {
+            connector
+                .set_certificate_file(cert, SslFiletype::PEM)
+                .unwrap();
+            connector
+                .set_private_key_file(key, SslFiletype::PEM)
+                .unwrap();
+        }
+        let connector = connector.build();
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut tls: SslStream<TcpStream> = connector.connect("localhost", stream).ok()?;
+        tls.write_all(b"GET /-/healthy HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .ok()?;
+
+        if let Ok(Some(r)) = server.recv_timeout(Duration::from_secs(2)) {
+            r.respond(tiny_http::Response::from_string("Healthy\n"))
+                .unwrap();
+        }
+
+        let mut reply = String::new();
+        match tls.read_to_string(&mut reply) {
+            Ok(_) if !reply.is_empty() => Some(reply),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tls_requires_valid_client_certificate() {
+        let pki = Pki::new("require");
+        let rogue = Pki::new("rogue");
+        let (server, port) = tls_server(&pki, constants::WEB_CLIENT_AUTH_REQUIRE_AND_VERIFY);
+        let (cert, key) = pki.issue("client");
+        let (rogue_cert, rogue_key) = rogue.issue("client");
+
+        let reply = request(&pki, port, &server, Some((&cert, &key))).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 200"));
+        assert!(reply.ends_with("Healthy\n"));
+
+        assert_eq!(request(&pki, port, &server, None), None);
+        assert_eq!(
+            request(&pki, port, &server, Some((&rogue_cert, &rogue_key))),
+            None
+        );
+    }
+
+    #[test]
+    fn tls_verifies_client_certificate_if_given() {
+        let pki = Pki::new("ifgiven");
+        let rogue = Pki::new("ifgiven-rogue");
+        let (server, port) = tls_server(&pki, constants::WEB_CLIENT_AUTH_VERIFY_IF_GIVEN);
+        let (cert, key) = pki.issue("client");
+        let (rogue_cert, rogue_key) = rogue.issue("client");
+
+        assert!(request(&pki, port, &server, None).is_some());
+        assert!(request(&pki, port, &server, Some((&cert, &key))).is_some());
+        assert_eq!(
+            request(&pki, port, &server, Some((&rogue_cert, &rogue_key))),
+            None
+        );
+    }
+
+    #[test]
+    fn tls_backend_socket_is_private() {
+        let pki = Pki::new("private");
+        let (server, _) = tls_server(&pki, constants::WEB_CLIENT_AUTH_NONE);
+        let dir = server.private_dir.clone().unwrap();
+
+        let mode = fs::metadata(&dir).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o700);
+        assert!(dir.join(constants::WEB_TLS_BACKEND_SOCKET).exists());
+
+        drop(server);
+        assert!(!dir.exists());
+    }
+}
@@ -2,15 +2,42 @@ use crate::constants;
 use log::debug;
 use serde::Deserialize;
 use simple_error::bail;
+use std::collections::HashMap;
 use std::error::Error;
-use std::fs;
 use url::Url;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Configuration {
+    #[serde(default)]
+    pub conflicts: Conflicts,
+    #[serde(default)]
+    pub logging: global::logging::Logging,
     #[serde(default)]
     pub prometheus: Prometheus,
     pub mqtt: global::mqtt::MQTT,
+    #[serde(default)]
+    pub state: State,
+    #[serde(default)]
+    pub web: global::web::Web,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Conflicts {
+    #[serde(default)]
+    pub help: HashMap<String, String>,
+    #[serde(default)]
+    pub policy: ConflictPolicy,
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    #[default]
+    FirstSeen,
+    MostCommon,
+    Configured,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -19,6 +46,16 @@ pub struct Prometheus {
     pub listen: String,
     #[serde(default = "prometheus_default_path")]
     pub path: String,
+    #[serde(default = "prometheus_default_stale_retention")]
+    pub stale_retention: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub file: String,
+    #[serde(default = "state_default_interval")]
+    pub interval: u64,
 }
 
 impl Default for Prometheus {
@@ -26,6 +63,16 @@ impl Default for Prometheus {
         Prometheus {
             listen: constants::DEFAULT_LISTEN_ADDR.to_string(),
             path: constants::DEFAULT_METRICS_PATH.to_string(),
+            stale_retention: constants::DEFAULT_STALE_RETENTION,
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            file: String::new(),
+            interval: constants::DEFAULT_STATE_INTERVAL,
         }
     }
 }
@@ -38,9 +85,18 @@ fn prometheus_default_path() -> String {
     constants::DEFAULT_METRICS_PATH.to_string()
 }
 
+fn prometheus_default_stale_retention() -> i64 {
+    constants::DEFAULT_STALE_RETENTION
+}
+
+fn state_default_interval() -> u64 {
+    constants::DEFAULT_STATE_INTERVAL
+}
+
 pub fn parse_config_file(f: &str) -> Result<Configuration, Box<dyn Error>> {
-    let raw = fs::read_to_string(f)?;
-    let parsed: Configuration = serde_yaml::from_str(raw.as_str())?;
+    let raw = global::config::read(f)?;
+    let mut parsed: Configuration = serde_yaml::from_value(raw)?;
+    global::mqtt::load_secrets(&mut parsed.mqtt)?;
 
     validate(&parsed)?;
 
@@ -69,6 +125,27 @@ fn validate(cfg: &Configuration) -> Result<(), Box<dyn Error>> {
     if cfg.prometheus.path.is_empty() {
         bail!("invalid metrics path");
     }
+    if let Err(e) = global::logging::validate(&cfg.logging) {
+        bail!("invalid logging configuration - {}", e);
+    }
+
+    if let Err(e) = global::web::validate(&cfg.web) {
+        bail!("invalid web configuration - {}", e);
+    }
+    if cfg.prometheus.stale_retention < 0 {
+        bail!("invalid retention time for stale sources");
+    }
+
+    for (name, mtype) in cfg.conflicts.types.iter() {
+        match mtype.as_str() {
+            "counter" | "gauge" | "histogram" | "summary" | "untyped" => {}
+            _ => bail!("invalid metric type {} configured for {}", mtype, name),
+        };
+    }
+
+    if !cfg.state.file.is_empty() && cfg.state.interval == 0 {
+        bail!("invalid state snapshot interval");
+    }
 
     Ok(())
 }
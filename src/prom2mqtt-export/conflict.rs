@@ -0,0 +1,215 @@
+use crate::config;
+use crate::exporter;
+
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+
+pub struct Resolved {
+    // winning TYPE and HELP for every metric family
+    pub help: HashMap<String, String>,
+    pub types: HashMap<String, String>,
+    // (source, metric name) pairs that must not be exported because the TYPE of the source lost
+    pub excluded: HashSet<(String, String)>,
+}
+
+struct Candidate<'a> {
+    source: &'a str,
+    value: &'a str,
+}
+
+pub fn resolve(
+    metrics: &HashMap<String, global::payload::Message>,
+    first_seen: &HashMap<String, i64>,
+    cfg: &config::Conflicts,
+    known_conflicts: &mut HashSet<String>,
+) -> Resolved {
+    let mut result = Resolved {
+        help: HashMap::new(),
+        types: HashMap::new(),
+        excluded: HashSet::new(),
+    };
+    let mut type_candidates: HashMap<&str, Vec<Candidate>> = HashMap::new();
+    let mut help_candidates: HashMap<&str, Vec<Candidate>> = HashMap::new();
+    let mut families: HashSet<&str> = HashSet::new();
+    let mut conflicts: HashSet<String> = HashSet::new();
+
+    // Visit sources in the order they were first seen, the name breaks ties. This makes the
+    // resolution independent of the iteration order of the HashMap
+    let mut sources: Vec<&String> = metrics.keys().collect();
+    sources.sort_by(|a, b| {
+        let fa = first_seen.get(*a).unwrap_or(&i64::MAX);
+        let fb = first_seen.get(*b).unwrap_or(&i64::MAX);
+        fa.cmp(fb).then_with(|| a.cmp(b))
+    });
+
+    for source in sources.iter() {
+        if let Some(mdata) = metrics.get(*source) {
+            for mtrc in mdata.payload.iter() {
+                if mtrc.data_type.is_empty() && mtrc.help.is_empty() {
+                    continue;
+                }
+                families.insert(&mtrc.metric_name);
+                if !mtrc.data_type.is_empty() {
+                    type_candidates
+                        .entry(&mtrc.metric_name)
+                        .or_default()
+                        .push(Candidate {
+                            source,
+                            value: &mtrc.data_type,
+                        });
+                }
+                if !mtrc.help.is_empty() {
+                    help_candidates
+                        .entry(&mtrc.metric_name)
+                        .or_default()
+                        .push(Candidate {
+                            source,
+                            value: &mtrc.help,
+                        });
+                }
+            }
+        }
+    }
+
+    exporter::METADATA_CONFLICTS.reset();
+
+    for name in families {
+        let mtype = match type_candidates.get(name) {
+            Some(v) => {
+                let winner = select(name, v, &cfg.policy, &cfg.types);
+                let distinct = count_distinct(v);
+                if distinct > 1 {
+                    let key = format!("{}/type", name);
+                    exporter::METADATA_CONFLICTS
+                        .with_label_values(&[name, "type"])
+                        .set(distinct as i64);
+                    if !known_conflicts.contains(&key) {
+                        warn!(
+                            "conflicting TYPE for {} ({}), using {}",
+                            name,
+                            describe(v),
+                            winner
+                        );
+                    }
+
+                    // samples of the losing sources don't match the TYPE, drop them
+                    for c in v.iter().filter(|c| c.value != winner) {
+                        for series in family_series(name, c.value) {
+                            debug!(
+                                "excluding {} from {} because of conflicting TYPE",
+                                series, c.source
+                            );
+                            result.excluded.insert((c.source.to_string(), series));
+                        }
+                    }
+                    conflicts.insert(key);
+                }
+                winner
+            }
+            None => String::new(),
+        };
+
+        let mhelp = match help_candidates.get(name) {
+            Some(v) => {
+                let winner = select(name, v, &cfg.policy, &cfg.help);
+                let distinct = count_distinct(v);
+                if distinct > 1 {
+                    let key = format!("{}/help", name);
+                    exporter::METADATA_CONFLICTS
+                        .with_label_values(&[name, "help"])
+                        .set(distinct as i64);
+                    if !known_conflicts.contains(&key) {
+                        warn!(
+                            "conflicting HELP for {} ({}), using \"{}\"",
+                            name,
+                            describe(v),
+                            winner
+                        );
+                    }
+                    conflicts.insert(key);
+                }
+                winner
+            }
+            None => String::new(),
+        };
+
+        result.types.insert(name.to_string(), mtype);
+        result.help.insert(name.to_string(), mhelp);
+    }
+
+    for resolved in known_conflicts.difference(&conflicts) {
+        info!("conflict for {} has been resolved", resolved);
+    }
+    *known_conflicts = conflicts;
+
+    result
+}
+
+fn select(
+    name: &str,
+    candidates: &[Candidate],
+    policy: &config::ConflictPolicy,
+    configured: &HashMap<String, String>,
+) -> String {
+    match policy {
+        config::ConflictPolicy::FirstSeen => candidates[0].value.to_string(),
+        config::ConflictPolicy::MostCommon => {
+            let mut count: HashMap<&str, usize> = HashMap::new();
+            for c in candidates.iter() {
+                *count.entry(c.value).or_default() += 1;
+            }
+            // candidates are ordered by first seen, so ties are resolved by the first seen value
+            let mut winner = candidates[0].value;
+            for c in candidates.iter() {
+                if count[c.value] > count[winner] {
+                    winner = c.value;
+                }
+            }
+            winner.to_string()
+        }
+        // a configured value no source reports would drop all samples of the metric, fall back to first seen
+        config::ConflictPolicy::Configured => match configured.get(name) {
+            Some(v) if candidates.iter().any(|c| c.value == v) => v.to_string(),
+            Some(v) => {
+                debug!(
+                    "configured value \"{}\" for {} is not reported by any source, using the first seen value",
+                    v, name
+                );
+                candidates[0].value.to_string()
+            }
+            None => candidates[0].value.to_string(),
+        },
+    }
+}
+
+fn count_distinct(candidates: &[Candidate]) -> usize {
+    candidates
+        .iter()
+        .map(|c| c.value)
+        .collect::<HashSet<&str>>()
+        .len()
+}
+
+fn describe(candidates: &[Candidate]) -> String {
+    candidates
+        .iter()
+        .map(|c| format!("{}: \"{}\"", c.source, c.value))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn family_series(name: &str, mtype: &str) -> Vec<String> {
+    match mtype {
+        "histogram" => vec![
+            format!("{}_bucket", name),
+            format!("{}_count", name),
+            format!("{}_sum", name),
+        ],
+        "summary" => vec![
+            name.to_string(),
+            format!("{}_count", name),
+            format!("{}_sum", name),
+        ],
+        _ => vec![name.to_string()],
+    }
+}
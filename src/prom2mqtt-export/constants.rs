@@ -1,9 +1,14 @@
 pub const DEFAULT_CONFIG_FILE: &str = "/etc/prometheus-mqtt-transport/export.yaml";
 pub const DEFAULT_LISTEN_ADDR: &str = "localhost:9991";
 pub const DEFAULT_METRICS_PATH: &str = "/metrics";
-pub const HTML_ROOT: &str = "<html>\n<head><title>Prometheus MQTT transport</title></head>\n<body>\n<h1>Prometheus MQTT transport</h1>\n<p><a href=\"/metrics\">Metrics</a></p>\n</body>\n</html>\n";
+pub const DEFAULT_STALE_RETENTION: i64 = 3600;
+pub const DEFAULT_STATE_INTERVAL: u64 = 60;
+pub const API_SOURCES_PATH: &str = "/api/v1/sources";
+pub const HTML_ROOT: &str = "<html>\n<head><title>Prometheus MQTT transport</title></head>\n<body>\n<h1>Prometheus MQTT transport</h1>\n<p><a href=\"/metrics\">Metrics</a></p>\n<p><a href=\"/api/v1/sources\">Sources</a></p>\n<p><a href=\"/-/healthy\">Health</a></p>\n<p><a href=\"/-/ready\">Readiness</a></p>\n</body>\n</html>\n";
 pub const HTTP_NOT_FOUND: &str = "Not found";
 pub const HTTP_METHOD_NOT_ALLOWED: &str = "Method not allowed";
+pub const HTTP_UNAUTHORIZED: &str = "Unauthorized";
+pub const HTTP_NOT_SUBSCRIBED: &str = "Not subscribed to MQTT topic\n";
 
 pub const METRICS_BYTES_RECEIVED_NO_COMP_TOTAL_HELP: &str = "Bytes of uncompressed metric received";
 pub const METRICS_BYTES_RECEIVED_NO_COMP_TOTAL_NAME: &str =
@@ -37,3 +42,17 @@ pub const METRICS_PAYLOAD_PARSE_TIME_BUCKETS: &[f64; 23] = &[
     0.01, 0.05, 0.1, 0.15, 0.2, 0.25, 0.3, 0.35, 0.4, 0.45, 0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8,
     0.85, 0.9, 0.95, 1.0, 1.5, 2.0,
 ];
+
+pub const METRICS_SOURCE_UP_NAME: &str = "prom2mqtt_export_source_up";
+pub const METRICS_SOURCE_UP_HELP: &str = "Whether data of the source is current (1) or expired (0)";
+pub const METRICS_SOURCE_LAST_UPDATE_NAME: &str =
+    "prom2mqtt_export_source_last_update_timestamp_seconds";
+pub const METRICS_SOURCE_LAST_UPDATE_HELP: &str =
+    "UNIX timestamp of the last data received from the source";
+pub const METRICS_SOURCE_AGE_NAME: &str = "prom2mqtt_export_source_age_seconds";
+pub const METRICS_SOURCE_AGE_HELP: &str =
+    "Seconds since the last data was received from the source";
+
+pub const METRICS_METADATA_CONFLICTS_NAME: &str = "prom2mqtt_export_metadata_conflicts";
+pub const METRICS_METADATA_CONFLICTS_HELP: &str =
+    "Number of different values for HELP or TYPE of a metric received from different sources";
@@ -1,7 +1,11 @@
+use crate::config;
+use crate::conflict;
 use crate::exporter;
+use crate::state;
 
 use flate2::bufread::GzDecoder;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use simple_error::bail;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
@@ -9,170 +13,128 @@ use std::io::prelude::*;
 use std::string::String;
 use std::sync::mpsc;
 
+// MetricData keeps its original name even though it repeats the name of the enum
+#[allow(clippy::enum_variant_names)]
 pub enum Data {
     HTTPRequest,
-    MetricData(Vec<global::payload::Message>),
+    MetricData(Vec<global::payload::Message>, PayloadInfo),
+    Reload(Box<config::Configuration>),
+    SaveState,
+    Shutdown,
+    SourcesRequest(mpsc::Sender<Result<String, String>>),
 }
 
-fn build_reply_string(metrics: &HashMap<String, global::payload::Message>) -> String {
-    // HashSets hold *basenames* of histogram and summaries
-    let mut histogram: HashSet<String> = HashSet::new();
-    let mut summary: HashSet<String> = HashSet::new();
+#[derive(Clone, Debug)]
+pub struct PayloadInfo {
+    pub compressed: bool,
+    pub size: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StaleSource {
+    pub expired: i64,
+    pub last_update: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct SourcesReply {
+    status: String,
+    data: Vec<SourceStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct SourceStatus {
+    name: String,
+    // the last message reported a failed scrape, last_success is the time of the last good data
+    failed: bool,
+    last_success: i64,
+    last_update: i64,
+    expiration: i64,
+    purge_in: i64,
+    metric_families: usize,
+    samples: usize,
+    payload_size: usize,
+    compressed: bool,
+}
+
+fn build_reply_string(
+    metrics: &HashMap<String, global::payload::Message>,
+    first_seen: &HashMap<String, i64>,
+    cfg: &config::Conflicts,
+    known_conflicts: &mut HashSet<String>,
+) -> String {
     let mut result: Vec<String> = Vec::new();
-    // HELP and TYPE strings *must* occur only once!
-    let mut metrics_type: HashMap<String, String> = HashMap::new();
-    let mut metrics_help: HashMap<String, String> = HashMap::new();
-    let mut metrics_data: HashMap<String, Vec<String>> = HashMap::new();
+    let mut metrics_data: HashMap<&str, Vec<&str>> = HashMap::new();
     let parse_time = std::time::Instant::now();
 
-    debug!("scanning for histogram and summary metrics");
-    // get <basename> of histogram and summary metrics
-    for name in metrics.keys() {
-        if let Some(mdata) = metrics.get(name) {
-            for mtrc in mdata.payload.iter() {
-                debug!("checking TYPE for '{:?}'", mtrc);
-                match mtrc.data_type.as_str() {
-                    "histogram" => {
-                        /*
-                         * "A histogram with a base metric name of <basename> exposes multiple time series during a scrape:
-                         *  cumulative counters for the observation buckets, exposed as <basename>_bucket{le="<upper inclusive bound>"}
-                         *  the total sum of all observed values, exposed as <basename>_sum
-                         *  the count of events that have been observed, exposed as <basename>_count (identical to <basename>_bucket{le="+Inf"} above)"
-                         *
-                         *  see: https://prometheus.io/docs/concepts/metric_types/#histogram
-                         *
-                         *  e.g.:
-                         *
-                         *   # HELP bind_resolver_query_duration_seconds Resolver query round-trip time in seconds.
-                         *   # TYPE bind_resolver_query_duration_seconds histogram
-                         *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.01"} 0
-                         *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.1"} 0
-                         *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.5"} 0
-                         *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.8"} 0
-                         *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="1.6"} 0
-                         *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="+Inf"} 0
-                         *   bind_resolver_query_duration_seconds_sum{view="_bind"} NaN
-                         *   bind_resolver_query_duration_seconds_count{view="_bind"} 0
-                         *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.01"} 109879
-                         *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.1"} 601436
-                         *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.5"} 774852
-                         *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.8"} 775299
-                         *   bind_resolver_query_duration_seconds_bucket{view="_default",le="1.6"} 775323
-                         *   bind_resolver_query_duration_seconds_bucket{view="_default",le="+Inf"} 775365
-                         *   bind_resolver_query_duration_seconds_sum{view="_default"} NaN
-                         *   bind_resolver_query_duration_seconds_count{view="_default"} 775365
-                         *
-                         */
-                        histogram.insert(mtrc.metric_name.clone());
-                    }
-                    "summary" => {
-                        /*
-                         * "A summary with a base metric name of <basename> exposes multiple time series during a scrape:
-                         *  streaming φ-quantiles (0 ≤ φ ≤ 1) of observed events, exposed as <basename>{quantile="<φ>"}
-                         *  the total sum of all observed values, exposed as <basename>_sum
-                         *  the count of events that have been observed, exposed as <basename>_count"
-                         *
-                         * see: https://prometheus.io/docs/concepts/metric_types/#summary
-                         *
-                         * e.g.:
-                         *
-                         *  # HELP go_gc_duration_seconds A summary of the pause duration of garbage collection cycles.
-                         *  # TYPE go_gc_duration_seconds summary
-                         *  go_gc_duration_seconds{quantile="0"} 2.499e-05
-                         *  go_gc_duration_seconds{quantile="0.25"} 6.8457e-05
-                         *  go_gc_duration_seconds{quantile="0.5"} 8.2795e-05
-                         *  go_gc_duration_seconds{quantile="0.75"} 0.000126954
-                         *  go_gc_duration_seconds{quantile="1"} 0.000683124
-                         *  go_gc_duration_seconds_sum 5.7718011449999995
-                         *  go_gc_duration_seconds_count 44174
-                         *
-                         */
-                        summary.insert(mtrc.metric_name.clone());
-                    }
-                    _ => {}
-                };
-            }
-        }
-    }
-    debug!("found {} histogram types: {:?}", histogram.len(), histogram);
-    debug!("found {} summary types: {:?}", summary.len(), summary);
+    // HELP and TYPE strings *must* occur only once! Different sources may disagree on them,
+    // resolve conflicts first
+    let resolved = conflict::resolve(metrics, first_seen, cfg, known_conflicts);
 
-    // collect TYPE and HELP texts
-    for name in metrics.keys() {
+    // collect metric data, sources are visited ordered by their name to get a stable order of samples
+    let mut sources: Vec<&String> = metrics.keys().collect();
+    sources.sort();
+    for name in sources {
         if let Some(mdata) = metrics.get(name) {
             for mtrc in mdata.payload.iter() {
-                metrics_type.insert(mtrc.metric_name.to_string(), mtrc.data_type.clone());
-                metrics_help.insert(mtrc.metric_name.to_string(), mtrc.help.clone());
-                let collected_data = metrics_data
-                    .entry(mtrc.metric_name.to_string())
-                    .or_default();
-                collected_data.extend(mtrc.data.clone());
+                if resolved
+                    .excluded
+                    .contains(&(name.to_string(), mtrc.metric_name.to_string()))
+                {
+                    continue;
+                }
+                let collected_data = metrics_data.entry(&mtrc.metric_name).or_default();
+                collected_data.extend(mtrc.data.iter().map(|d| d.as_str()));
                 debug!(
-                    "collected type ({}), help ({}) and data ({}) for '{}'",
-                    mtrc.data_type.clone(),
-                    mtrc.help.clone(),
-                    mtrc.data.clone().len(),
-                    mtrc.metric_name
+                    "collected data ({}) for '{}' from {}",
+                    mtrc.data.len(),
+                    mtrc.metric_name,
+                    name
                 );
             }
         }
     }
 
-    // Build results for non-histogram and non-summary data
-    for m in metrics_type.keys() {
-        if let Some(mtype) = metrics_type.get(m) {
-            if let Some(mhelp) = metrics_help.get(m) {
-                if let Some(mdata) = metrics_data.get(m) {
-                    // skip histogram and summary data, will be collected later
-                    if mtype != "histogram"
-                        && mtype != "summary"
-                        && !(mtype.is_empty() && mhelp.is_empty())
-                    {
-                        debug!(
-                            "'{}' - TYPE: {} / HELP: {} / len(data) = {}",
-                            m,
-                            mtype,
-                            mhelp,
-                            mdata.len()
-                        );
-                        result.push(format!(
-                            "# TYPE {} {}\n{}\n# HELP {} {}",
-                            m,
-                            mtype,
-                            mdata.join("\n"),
-                            m,
-                            mhelp,
-                        ));
-                    }
-                }
-            } else {
-                error!("key {} found in metrics_type map but not in metrics_help, invalid metric format?", m);
-            }
-        }
-    }
+    // Metric families are sorted by name. HELP and TYPE precede the samples of the family and
+    // all samples of a family are kept together
+    let mut families: Vec<&String> = resolved.types.keys().collect();
+    families.sort();
 
-    // build reply for histogram and summary types
-    for m in histogram.iter() {
-        if let Some(mtype) = metrics_type.get(m) {
-            if let Some(mhelp) = metrics_help.get(m) {
-                debug!("adding metric data for histogram type {}", m);
-                if let Err(e) = append_histogram_result(&metrics_data, m, mhelp, mtype, &mut result)
-                {
-                    error!("an error occured while processing histogram data - {}", e);
-                }
-            }
+    for m in families {
+        let mtype = match resolved.types.get(m) {
+            Some(v) if !v.is_empty() => v.as_str(),
+            _ => "untyped",
+        };
+        let mhelp = match resolved.help.get(m) {
+            Some(v) => v.as_str(),
+            None => "",
+        };
+
+        let samples = match mtype {
+            "histogram" => histogram_samples(&metrics_data, m),
+            "summary" => summary_samples(&metrics_data, m),
+            _ => match metrics_data.get(m.as_str()) {
+                Some(v) => v.clone(),
+                None => Vec::new(),
+            },
+        };
+        if samples.is_empty() {
+            debug!("no data found for {} of type {}, skipping", m, mtype);
+            continue;
         }
-    }
 
-    for m in summary.iter() {
-        if let Some(mtype) = metrics_type.get(m) {
-            if let Some(mhelp) = metrics_help.get(m) {
-                debug!("adding metric data for histogram type {}", m);
-                if let Err(e) = append_summary_result(&metrics_data, m, mhelp, mtype, &mut result) {
-                    error!("an error occured while processing summary data - {}", e);
-                }
-            }
+        debug!(
+            "'{}' - TYPE: {} / HELP: {} / len(data) = {}",
+            m,
+            mtype,
+            mhelp,
+            samples.len()
+        );
+        if !mhelp.is_empty() {
+            result.push(format!("# HELP {} {}", m, mhelp));
         }
+        result.push(format!("# TYPE {} {}", m, mtype));
+        result.extend(samples.iter().map(|l| l.to_string()));
     }
 
     // enforce final new line otherwise promtool will complain ("unexpected end of input stream")
@@ -184,109 +146,330 @@ fn build_reply_string(metrics: &HashMap<String, global::payload::Message>) -> St
     result.join("\n")
 }
 
-fn append_summary_result(
-    data: &HashMap<String, Vec<String>>,
-    name: &str,
-    help: &str,
-    mtype: &str,
-    result: &mut Vec<String>,
-) -> Result<(), Box<dyn Error>> {
-    // "summary" type requires "<basename>", "_count" and "_sum" data
-    let count = format!("{}_count", name);
-    let sum = format!("{}_sum", name);
-
-    let base_data = match data.get(name) {
-        Some(v) => v,
-        None => bail!(
-            "{} is of type summary but nor data found for {}",
-            name,
-            name
-        ),
-    };
+#[derive(Default)]
+struct SeriesGroup<'a> {
+    // buckets or quantiles with their upper bound or quantile
+    values: Vec<(f64, &'a str)>,
+    sum: Vec<&'a str>,
+    count: Vec<&'a str>,
+}
 
-    let count_data = match data.get(&count) {
-        Some(v) => v,
-        None => bail!(
-            "{} is of type summary but nor data found for {}",
-            name,
-            count
-        ),
-    };
+fn histogram_samples<'a>(data: &HashMap<&str, Vec<&'a str>>, name: &str) -> Vec<&'a str> {
+    /*
+     * "A histogram with a base metric name of <basename> exposes multiple time series during a scrape:
+     *  cumulative counters for the observation buckets, exposed as <basename>_bucket{le="<upper inclusive bound>"}
+     *  the total sum of all observed values, exposed as <basename>_sum
+     *  the count of events that have been observed, exposed as <basename>_count (identical to <basename>_bucket{le="+Inf"} above)"
+     *
+     *  see: https://prometheus.io/docs/concepts/metric_types/#histogram
+     *
+     *  e.g.:
+     *
+     *   # HELP bind_resolver_query_duration_seconds Resolver query round-trip time in seconds.
+     *   # TYPE bind_resolver_query_duration_seconds histogram
+     *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.01"} 0
+     *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.1"} 0
+     *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.5"} 0
+     *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="0.8"} 0
+     *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="1.6"} 0
+     *   bind_resolver_query_duration_seconds_bucket{view="_bind",le="+Inf"} 0
+     *   bind_resolver_query_duration_seconds_sum{view="_bind"} NaN
+     *   bind_resolver_query_duration_seconds_count{view="_bind"} 0
+     *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.01"} 109879
+     *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.1"} 601436
+     *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.5"} 774852
+     *   bind_resolver_query_duration_seconds_bucket{view="_default",le="0.8"} 775299
+     *   bind_resolver_query_duration_seconds_bucket{view="_default",le="1.6"} 775323
+     *   bind_resolver_query_duration_seconds_bucket{view="_default",le="+Inf"} 775365
+     *   bind_resolver_query_duration_seconds_sum{view="_default"} NaN
+     *   bind_resolver_query_duration_seconds_count{view="_default"} 775365
+     *
+     */
+    grouped_samples(
+        data,
+        &format!("{}_bucket", name),
+        "le",
+        &format!("{}_sum", name),
+        &format!("{}_count", name),
+    )
+}
+
+fn summary_samples<'a>(data: &HashMap<&str, Vec<&'a str>>, name: &str) -> Vec<&'a str> {
+    /*
+     * "A summary with a base metric name of <basename> exposes multiple time series during a scrape:
+     *  streaming φ-quantiles (0 ≤ φ ≤ 1) of observed events, exposed as <basename>{quantile="<φ>"}
+     *  the total sum of all observed values, exposed as <basename>_sum
+     *  the count of events that have been observed, exposed as <basename>_count"
+     *
+     * see: https://prometheus.io/docs/concepts/metric_types/#summary
+     *
+     * e.g.:
+     *
+     *  # HELP go_gc_duration_seconds A summary of the pause duration of garbage collection cycles.
+     *  # TYPE go_gc_duration_seconds summary
+     *  go_gc_duration_seconds{quantile="0"} 2.499e-05
+     *  go_gc_duration_seconds{quantile="0.25"} 6.8457e-05
+     *  go_gc_duration_seconds{quantile="0.5"} 8.2795e-05
+     *  go_gc_duration_seconds{quantile="0.75"} 0.000126954
+     *  go_gc_duration_seconds{quantile="1"} 0.000683124
+     *  go_gc_duration_seconds_sum 5.7718011449999995
+     *  go_gc_duration_seconds_count 44174
+     *
+     */
+    grouped_samples(
+        data,
+        name,
+        "quantile",
+        &format!("{}_sum", name),
+        &format!("{}_count", name),
+    )
+}
+
+fn grouped_samples<'a>(
+    data: &HashMap<&str, Vec<&'a str>>,
+    values: &str,
+    value_label: &str,
+    sum: &str,
+    count: &str,
+) -> Vec<&'a str> {
+    // Samples are grouped by their labels (without le/quantile), groups are kept in order of their appearance
+    let mut groups: Vec<SeriesGroup> = Vec::new();
+    let mut group_index: HashMap<String, usize> = HashMap::new();
+    let mut result: Vec<&str> = Vec::new();
 
-    let sum_data = match data.get(&sum) {
-        Some(v) => v,
-        None => bail!(
-            "{} is of type summary but nor data found for {}",
-            name,
-            count
-        ),
+    let mut group_of = |line: &str, groups: &mut Vec<SeriesGroup<'a>>| -> (usize, Option<f64>) {
+        let (key, value) = series_key(line, value_label);
+        let idx = *group_index.entry(key).or_insert_with(|| {
+            groups.push(SeriesGroup::default());
+            groups.len() - 1
+        });
+        (idx, value)
     };
 
-    result.push(format!("# TYPE {} {}", name, mtype));
-    result.push(base_data.join("\n"));
-    result.push(count_data.join("\n"));
-    result.push(sum_data.join("\n"));
-    result.push(format!("# HELP {} {}", name, help));
+    if let Some(lines) = data.get(values) {
+        for line in lines {
+            let (idx, value) = group_of(line, &mut groups);
+            groups[idx].values.push((value.unwrap_or(f64::NAN), line));
+        }
+    }
+    if let Some(lines) = data.get(sum) {
+        for line in lines {
+            let (idx, _) = group_of(line, &mut groups);
+            groups[idx].sum.push(line);
+        }
+    }
+    if let Some(lines) = data.get(count) {
+        for line in lines {
+            let (idx, _) = group_of(line, &mut groups);
+            groups[idx].count.push(line);
+        }
+    }
+
+    for group in groups.iter_mut() {
+        // buckets and quantiles in ascending order, "+Inf" last
+        group.values.sort_by(|a, b| a.0.total_cmp(&b.0));
+        result.extend(group.values.iter().map(|v| v.1));
+        result.extend(group.sum.iter());
+        result.extend(group.count.iter());
+    }
 
-    Ok(())
+    result
 }
 
-fn append_histogram_result(
-    data: &HashMap<String, Vec<String>>,
-    name: &str,
-    help: &str,
-    mtype: &str,
-    result: &mut Vec<String>,
-) -> Result<(), Box<dyn Error>> {
-    // "histogram" type requires "_bucket", "_count" and "_sum" data
-    let bucket = format!("{}_bucket", name);
-    let count = format!("{}_count", name);
-    let sum = format!("{}_sum", name);
-
-    let bucket_data = match data.get(&bucket) {
-        Some(v) => v,
-        None => bail!(
-            "{} is of type histogram but nor data found for {}",
-            name,
-            bucket
-        ),
-    };
+// Returns the labels of a sample without the label `skip` as sorted string and the numeric value of `skip`
+fn series_key(line: &str, skip: &str) -> (String, Option<f64>) {
+    let mut labels: Vec<String> = Vec::new();
+    let mut value: Option<f64> = None;
 
-    let count_data = match data.get(&count) {
-        Some(v) => v,
-        None => bail!(
-            "{} is of type histogram but nor data found for {}",
-            name,
-            count
-        ),
+    for (lname, lvalue) in parse_labels(line) {
+        if lname == skip {
+            value = match lvalue {
+                "+Inf" => Some(f64::INFINITY),
+                "-Inf" => Some(f64::NEG_INFINITY),
+                _ => lvalue.parse::<f64>().ok(),
+            };
+        } else {
+            labels.push(format!("{}=\"{}\"", lname, lvalue));
+        }
+    }
+    labels.sort();
+
+    (labels.join(","), value)
+}
+
+fn parse_labels(line: &str) -> Vec<(&str, &str)> {
+    let mut result: Vec<(&str, &str)> = Vec::new();
+    let bytes = line.as_bytes();
+    let mut pos = match line.find('{') {
+        Some(v) => v + 1,
+        None => return result,
     };
 
-    let sum_data = match data.get(&sum) {
-        Some(v) => v,
-        None => bail!(
-            "{} is of type histogram but nor data found for {}",
-            name,
-            count
-        ),
+    loop {
+        while pos < bytes.len() && (bytes[pos] == b',' || bytes[pos] == b' ') {
+            pos += 1;
+        }
+        if pos >= bytes.len() || bytes[pos] == b'}' {
+            break;
+        }
+
+        let eq = match line[pos..].find('=') {
+            Some(v) => pos + v,
+            None => break,
+        };
+        let lname = line[pos..eq].trim();
+
+        pos = eq + 1;
+        while pos < bytes.len() && bytes[pos] == b' ' {
+            pos += 1;
+        }
+        if pos >= bytes.len() || bytes[pos] != b'"' {
+            break;
+        }
+        pos += 1;
+
+        let start = pos;
+        while pos < bytes.len() && bytes[pos] != b'"' {
+            // skip escaped characters like \"
+            if bytes[pos] == b'\\' {
+                pos += 1;
+            }
+            pos += 1;
+        }
+        result.push((lname, &line[start..pos.min(bytes.len())]));
+        pos += 1;
+    }
+
+    result
+}
+
+fn build_sources_reply(
+    metrics: &HashMap<String, global::payload::Message>,
+    metrics_expiration: &HashMap<String, i64>,
+    metrics_payload: &HashMap<String, PayloadInfo>,
+    stale_sources: &HashMap<String, StaleSource>,
+    now: i64,
+) -> Result<String, Box<dyn Error>> {
+    let mut sources: Vec<SourceStatus> = Vec::new();
+
+    for (name, mdata) in metrics.iter() {
+        let last_update = match metrics_expiration.get(name) {
+            Some(v) => *v,
+            None => bail!(
+                "BUG: key {} found in metrics map but not in metrics_expiration",
+                name
+            ),
+        };
+        let (payload_size, compressed) = match metrics_payload.get(name) {
+            Some(v) => (v.size, v.compressed),
+            None => (0, false),
+        };
+
+        let last_success = match stale_sources.get(name) {
+            Some(v) if mdata.failed => v.last_update,
+            _ => last_update,
+        };
+
+        sources.push(SourceStatus {
+            name: name.to_string(),
+            failed: mdata.failed,
+            last_success,
+            last_update,
+            expiration: mdata.expiration,
+            purge_in: last_update + mdata.expiration - now,
+            metric_families: mdata
+                .payload
+                .iter()
+                .filter(|p| !(p.data_type.is_empty() && p.help.is_empty()))
+                .count(),
+            samples: mdata.payload.iter().map(|p| p.data.len()).sum(),
+            payload_size,
+            compressed,
+        });
+    }
+    sources.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let reply = SourcesReply {
+        status: "success".to_string(),
+        data: sources,
     };
+    Ok(serde_json::to_string(&reply)?)
+}
 
-    result.push(format!("# TYPE {} {}", name, mtype));
-    result.push(bucket_data.join("\n"));
-    result.push(count_data.join("\n"));
-    result.push(sum_data.join("\n"));
-    result.push(format!("# HELP {} {}", name, help));
+fn save_state(
+    cfg: &config::Configuration,
+    metrics: &HashMap<String, global::payload::Message>,
+    metrics_expiration: &HashMap<String, i64>,
+    metrics_payload: &HashMap<String, PayloadInfo>,
+    metrics_first_seen: &HashMap<String, i64>,
+    stale_sources: &HashMap<String, StaleSource>,
+) {
+    if cfg.state.file.is_empty() {
+        return;
+    }
 
-    Ok(())
+    debug!("saving state to {}", cfg.state.file);
+    if let Err(e) = state::save(
+        &cfg.state.file,
+        metrics,
+        metrics_expiration,
+        metrics_payload,
+        metrics_first_seen,
+        stale_sources,
+    ) {
+        error!("can't write state file {} - {}", cfg.state.file, e);
+    }
 }
 
 pub fn handler(
+    cfg: &config::Configuration,
     data_receiver: mpsc::Receiver<Data>,
     http_reply: mpsc::Sender<String>,
 ) -> Result<(), Box<dyn Error>> {
     let mut metrics: HashMap<String, global::payload::Message> = HashMap::new();
     let mut metrics_expiration: HashMap<String, i64> = HashMap::new();
+    let mut metrics_payload: HashMap<String, PayloadInfo> = HashMap::new();
+    let mut metrics_first_seen: HashMap<String, i64> = HashMap::new();
+    let mut stale_sources: HashMap<String, StaleSource> = HashMap::new();
+    let mut known_conflicts: HashSet<String> = HashSet::new();
+    let mut cfg = cfg.clone();
     let mut now: i64;
 
+    if !cfg.state.file.is_empty() {
+        now = chrono::Local::now().timestamp();
+        if std::path::Path::new(&cfg.state.file).exists() {
+            match state::load(&cfg.state.file, now) {
+                Ok(v) => {
+                    for src in v.sources {
+                        let mname = src.message.name.clone();
+                        metrics_payload.insert(
+                            mname.clone(),
+                            PayloadInfo {
+                                compressed: src.compressed,
+                                size: src.payload_size,
+                            },
+                        );
+                        metrics_expiration.insert(mname.clone(), src.last_update);
+                        metrics_first_seen.insert(mname.clone(), src.first_seen);
+                        metrics.insert(mname, src.message);
+                    }
+                    stale_sources = v.stale;
+                }
+                Err(e) => {
+                    warn!(
+                        "can't load state file {}, starting without saved data - {}",
+                        cfg.state.file, e
+                    );
+                }
+            };
+        } else {
+            info!(
+                "state file {} does not exist yet, starting without saved data",
+                cfg.state.file
+            );
+        }
+    }
+
     loop {
         let request = data_receiver.recv()?;
         now = chrono::Local::now().timestamp();
@@ -294,31 +477,137 @@ pub fn handler(
             Data::HTTPRequest => {
                 debug!("HTTP request received");
                 debug!("purging expired data");
-                purge_expired(&mut metrics, &mut metrics_expiration, now);
+                purge_expired(
+                    &mut metrics,
+                    &mut metrics_expiration,
+                    &mut metrics_payload,
+                    &mut metrics_first_seen,
+                    &mut stale_sources,
+                    now,
+                );
+                update_source_metrics(
+                    &metrics,
+                    &metrics_expiration,
+                    &mut stale_sources,
+                    cfg.prometheus.stale_retention,
+                    now,
+                );
 
-                let reply = build_reply_string(&metrics);
+                let reply = build_reply_string(
+                    &metrics,
+                    &metrics_first_seen,
+                    &cfg.conflicts,
+                    &mut known_conflicts,
+                );
                 http_reply.send(reply)?;
             }
-            Data::MetricData(msg) => {
+            Data::MetricData(msg, pinfo) => {
                 debug!("{} metric messages received", msg.len());
                 for m in msg {
                     let mname = m.name.clone();
+                    // the data of a failed scrape is replaced right away and the source is reported as stale
+                    if m.failed {
+                        info!("{} reported a failed scrape, dropping its data", mname);
+                        // keep the time of the last good data if the previous scrape failed too
+                        let last_update = match stale_sources.get(&mname) {
+                            Some(v) => v.last_update,
+                            None => metrics_expiration.get(&mname).copied().unwrap_or(now),
+                        };
+                        metrics.insert(mname.clone(), m);
+                        metrics_payload.insert(mname.clone(), pinfo.clone());
+                        metrics_first_seen.entry(mname.clone()).or_insert(now);
+                        metrics_expiration.insert(mname.clone(), now);
+                        stale_sources.insert(
+                            mname,
+                            StaleSource {
+                                expired: now,
+                                last_update,
+                            },
+                        );
+                        continue;
+                    }
                     metrics.insert(m.name.clone(), m);
+                    metrics_payload.insert(mname.clone(), pinfo.clone());
+                    metrics_first_seen.entry(mname.clone()).or_insert(now);
+                    if stale_sources.remove(&mname).is_some() {
+                        info!("{} is sending data again", mname);
+                    }
                     metrics_expiration.insert(mname, now);
                 }
             }
+            Data::Reload(v) => {
+                debug!("applying new configuration to data handler");
+                cfg = *v;
+            }
+            Data::SaveState => {
+                save_state(
+                    &cfg,
+                    &metrics,
+                    &metrics_expiration,
+                    &metrics_payload,
+                    &metrics_first_seen,
+                    &stale_sources,
+                );
+            }
+            Data::Shutdown => {
+                info!("shutting down data handler");
+                save_state(
+                    &cfg,
+                    &metrics,
+                    &metrics_expiration,
+                    &metrics_payload,
+                    &metrics_first_seen,
+                    &stale_sources,
+                );
+                return Ok(());
+            }
+            Data::SourcesRequest(reply) => {
+                debug!("source list request received");
+                purge_expired(
+                    &mut metrics,
+                    &mut metrics_expiration,
+                    &mut metrics_payload,
+                    &mut metrics_first_seen,
+                    &mut stale_sources,
+                    now,
+                );
+
+                // a broken source list must not take down the /metrics endpoint
+                let result = match build_sources_reply(
+                    &metrics,
+                    &metrics_expiration,
+                    &metrics_payload,
+                    &stale_sources,
+                    now,
+                ) {
+                    Ok(v) => Ok(v),
+                    Err(e) => {
+                        error!("can't build source list - {}", e);
+                        Err(e.to_string())
+                    }
+                };
+                if reply.send(result).is_err() {
+                    error!("can't send source list to HTTP thread");
+                }
+            }
         };
     }
 }
 
-pub fn parse_raw_metrics(raw: Vec<u8>) -> Result<Vec<global::payload::Message>, Box<dyn Error>> {
+pub fn parse_raw_metrics(
+    raw: Vec<u8>,
+) -> Result<(Vec<global::payload::Message>, PayloadInfo), Box<dyn Error>> {
     if raw.len() < 2 {
         bail!("received payload is too short");
     }
 
     let prc = std::time::Instant::now();
+    let pinfo = PayloadInfo {
+        compressed: raw[0] == 0x1f && raw[1] == 0x8b,
+        size: raw.len(),
+    };
 
-    let data_str = if raw[0] == 0x1f && raw[1] == 0x8b {
+    let data_str = if pinfo.compressed {
         let dcomp = std::time::Instant::now();
 
         exporter::MESSAGES_RECEIVED_COMP_TOTAL.inc();
@@ -354,15 +643,18 @@ pub fn parse_raw_metrics(raw: Vec<u8>) -> Result<Vec<global::payload::Message>,
     exporter::PAYLOAD_PARSE_TIME.observe(prc_elapsed);
     info!("payload parsed in {} seconds", prc_elapsed);
 
-    Ok(parsed)
+    Ok((parsed, pinfo))
 }
 
 fn purge_expired(
     metrics: &mut HashMap<String, global::payload::Message>,
     metrics_expiration: &mut HashMap<String, i64>,
+    metrics_payload: &mut HashMap<String, PayloadInfo>,
+    metrics_first_seen: &mut HashMap<String, i64>,
+    stale_sources: &mut HashMap<String, StaleSource>,
     now: i64,
 ) {
-    let mut expired: Vec<String> = Vec::new();
+    let mut expired: Vec<(String, StaleSource)> = Vec::new();
 
     for name in metrics.keys() {
         if let Some(data) = metrics.get(name) {
@@ -374,7 +666,16 @@ fn purge_expired(
                         now - last_update
                     );
                     debug!("'{}' was last updated {} - {} seconds ago, expiration set to {}, adding to removal list", name, last_update, now - last_update, data.expiration);
-                    expired.push(name.to_string());
+                    // a source that reported a failed scrape is already stale since the failure and keeps
+                    // the time of its last good data
+                    let stale = match stale_sources.get(name) {
+                        Some(v) if data.failed => v.clone(),
+                        _ => StaleSource {
+                            expired: last_update + data.expiration,
+                            last_update: *last_update,
+                        },
+                    };
+                    expired.push((name.to_string(), stale));
                 }
             } else {
                 // XXX: Should never happen
@@ -386,9 +687,365 @@ fn purge_expired(
         }
     }
 
-    for exp in expired.iter() {
+    for (exp, stale) in expired {
         debug!("removing expired data for {} from HashMaps", exp);
-        metrics.remove(exp);
-        metrics_expiration.remove(exp);
+        metrics.remove(&exp);
+        metrics_expiration.remove(&exp);
+        metrics_payload.remove(&exp);
+        metrics_first_seen.remove(&exp);
+        stale_sources.insert(exp, stale);
+    }
+}
+
+fn update_source_metrics(
+    metrics: &HashMap<String, global::payload::Message>,
+    metrics_expiration: &HashMap<String, i64>,
+    stale_sources: &mut HashMap<String, StaleSource>,
+    retention: i64,
+    now: i64,
+) {
+    for name in metrics.keys() {
+        // sources that reported a failed scrape are handled as stale sources below
+        if stale_sources.contains_key(name) {
+            continue;
+        }
+        if let Some(last_update) = metrics_expiration.get(name) {
+            exporter::SOURCE_UP.with_label_values(&[name]).set(1);
+            exporter::SOURCE_LAST_UPDATE
+                .with_label_values(&[name])
+                .set(*last_update);
+            exporter::SOURCE_AGE
+                .with_label_values(&[name])
+                .set(now - last_update);
+        }
+    }
+
+    let mut forget: Vec<String> = Vec::new();
+    for (name, stale) in stale_sources.iter() {
+        if now - stale.expired >= retention {
+            debug!(
+                "'{}' expired at {}, retention of {} seconds exceeded, removing source metrics",
+                name, stale.expired, retention
+            );
+            forget.push(name.to_string());
+            continue;
+        }
+
+        exporter::SOURCE_UP.with_label_values(&[name]).set(0);
+        exporter::SOURCE_LAST_UPDATE
+            .with_label_values(&[name])
+            .set(stale.last_update);
+        exporter::SOURCE_AGE
+            .with_label_values(&[name])
+            .set(now - stale.last_update);
+    }
+
+    for name in forget {
+        stale_sources.remove(&name);
+        // Errors only signal the absence of the label set, which is fine
+        let _ = exporter::SOURCE_UP.remove_label_values(&[&name]);
+        let _ = exporter::SOURCE_LAST_UPDATE.remove_label_values(&[&name]);
+        let _ = exporter::SOURCE_AGE.remove_label_values(&[&name]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    fn payload(name: &str, mtype: &str, help: &str, data: &[&str]) -> global::payload::Payload {
+        global::payload::Payload {
+            data: data.iter().map(|d| d.to_string()).collect(),
+            data_type: mtype.to_string(),
+            help: help.to_string(),
+            metric_name: name.to_string(),
+        }
+    }
+
+    fn source(name: &str, payload: Vec<global::payload::Payload>) -> global::payload::Message {
+        global::payload::Message {
+            expiration: 60,
+            failed: false,
+            name: name.to_string(),
+            payload,
+        }
+    }
+
+    fn sources() -> Vec<global::payload::Message> {
+        vec![
+            source(
+                "node1",
+                vec![
+                    payload("up_time", "gauge", "Uptime of the system", &[]),
+                    payload("up_time", "", "", &["up_time{src=\"node1\"} 1"]),
+                    payload(
+                        "req_seconds_bucket",
+                        "",
+                        "",
+                        &[
+                            "req_seconds_bucket{src=\"node1\",le=\"+Inf\"} 3",
+                            "req_seconds_bucket{src=\"node1\",le=\"0.5\"} 2",
+                            "req_seconds_bucket{src=\"node1\",le=\"0.1\"} 1",
+                        ],
+                    ),
+                    payload(
+                        "req_seconds_count",
+                        "",
+                        "",
+                        &["req_seconds_count{src=\"node1\"} 3"],
+                    ),
+                    payload(
+                        "req_seconds_sum",
+                        "",
+                        "",
+                        &["req_seconds_sum{src=\"node1\"} 0.7"],
+                    ),
+                    payload("req_seconds", "histogram", "Request duration", &[]),
+                ],
+            ),
+            source(
+                "node2",
+                vec![
+                    payload(
+                        "gc_seconds_sum",
+                        "",
+                        "",
+                        &["gc_seconds_sum{src=\"node2\"} 1.5"],
+                    ),
+                    payload(
+                        "gc_seconds_count",
+                        "",
+                        "",
+                        &["gc_seconds_count{src=\"node2\"} 7"],
+                    ),
+                    payload(
+                        "gc_seconds",
+                        "summary",
+                        "GC pauses",
+                        &[
+                            "gc_seconds{src=\"node2\",quantile=\"1\"} 0.9",
+                            "gc_seconds{src=\"node2\",quantile=\"0\"} 0.01",
+                            "gc_seconds{src=\"node2\",quantile=\"0.5\"} 0.2",
+                        ],
+                    ),
+                    payload(
+                        "up_time",
+                        "gauge",
+                        "Uptime of the system",
+                        &["up_time{src=\"node2\"} 2"],
+                    ),
+                ],
+            ),
+        ]
+    }
+
+    fn render(order: &[usize]) -> String {
+        let mut metrics: HashMap<String, global::payload::Message> = HashMap::new();
+        let mut first_seen: HashMap<String, i64> = HashMap::new();
+        for (i, msg) in sources().into_iter().enumerate() {
+            if order.contains(&i) {
+                first_seen.insert(msg.name.clone(), 1);
+                metrics.insert(msg.name.clone(), msg);
+            }
+        }
+        build_reply_string(
+            &metrics,
+            &first_seen,
+            &config::Conflicts::default(),
+            &mut HashSet::new(),
+        )
+    }
+
+    // Checks the rules enforced by "promtool check metrics" on the text exposition format
+    fn check_exposition(text: &str) -> Result<(), String> {
+        let mut seen_families: HashSet<String> = HashSet::new();
+        let mut current = String::new();
+        let mut types: HashMap<String, String> = HashMap::new();
+
+        if !text.ends_with('\n') {
+            return Err("unexpected end of input stream".to_string());
+        }
+
+        for line in text.lines() {
+            if let Some(meta) = line.strip_prefix("# ") {
+                let fields: Vec<&str> = meta.splitn(3, ' ').collect();
+                let family = fields[1].to_string();
+                if family != current {
+                    if seen_families.contains(&family) {
+                        return Err(format!("second HELP/TYPE line for {}", family));
+                    }
+                    seen_families.insert(family.clone());
+                    current = family.clone();
+                }
+                if fields[0] == "TYPE" {
+                    if types.contains_key(&family) {
+                        return Err(format!("second TYPE line for {}", family));
+                    }
+                    types.insert(family, fields[2].to_string());
+                }
+                continue;
+            }
+
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = match types.get(&current).map(|t| t.as_str()) {
+                Some("histogram") => name
+                    .strip_suffix("_bucket")
+                    .or_else(|| name.strip_suffix("_sum"))
+                    .or_else(|| name.strip_suffix("_count"))
+                    .unwrap_or(name),
+                Some("summary") => name
+                    .strip_suffix("_sum")
+                    .or_else(|| name.strip_suffix("_count"))
+                    .unwrap_or(name),
+                _ => name,
+            };
+            if family != current {
+                return Err(format!("sample {} outside of its metric family", name));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reply_is_deterministic() {
+        let first = render(&[0, 1]);
+        for _ in 0..20 {
+            assert_eq!(first, render(&[1, 0]));
+        }
+    }
+
+    #[test]
+    fn reply_is_spec_ordered() {
+        let reply = render(&[0, 1]);
+        let expected = "# HELP gc_seconds GC pauses
+# TYPE gc_seconds summary
+gc_seconds{src=\"node2\",quantile=\"0\"} 0.01
+gc_seconds{src=\"node2\",quantile=\"0.5\"} 0.2
+gc_seconds{src=\"node2\",quantile=\"1\"} 0.9
+gc_seconds_sum{src=\"node2\"} 1.5
+gc_seconds_count{src=\"node2\"} 7
+# HELP req_seconds Request duration
+# TYPE req_seconds histogram
+req_seconds_bucket{src=\"node1\",le=\"0.1\"} 1
+req_seconds_bucket{src=\"node1\",le=\"0.5\"} 2
+req_seconds_bucket{src=\"node1\",le=\"+Inf\"} 3
+req_seconds_sum{src=\"node1\"} 0.7
+req_seconds_count{src=\"node1\"} 3
+# HELP up_time Uptime of the system
+# TYPE up_time gauge
+up_time{src=\"node1\"} 1
+up_time{src=\"node2\"} 2
+";
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn reply_is_promtool_compatible() {
+        let reply = render(&[0, 1]);
+        if let Err(e) = check_exposition(&reply) {
+            panic!("invalid exposition format: {}\n{}", e, reply);
+        }
+
+        // Use the real thing if it is installed
+        let promtool = Command::new("promtool")
+            .args(["check", "metrics"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        if let Ok(mut child) = promtool {
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(reply.as_bytes())
+                .unwrap();
+            let output = child.wait_with_output().unwrap();
+            assert!(
+                output.status.success(),
+                "promtool check metrics failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    // State after a source sent good data at 1000 and a failure marker at 1100
+    #[allow(clippy::type_complexity)]
+    fn failed_source() -> (
+        HashMap<String, global::payload::Message>,
+        HashMap<String, i64>,
+        HashMap<String, PayloadInfo>,
+        HashMap<String, i64>,
+        HashMap<String, StaleSource>,
+    ) {
+        let mut marker = source("failing", vec![payload("up", "gauge", "up", &["up 0"])]);
+        marker.failed = true;
+        marker.expiration = 60;
+        let good = source("good", vec![payload("up", "gauge", "up", &["up 1"])]);
+        let info = PayloadInfo {
+            compressed: false,
+            size: 42,
+        };
+
+        (
+            HashMap::from([("failing".to_string(), marker), ("good".to_string(), good)]),
+            HashMap::from([("failing".to_string(), 1100), ("good".to_string(), 1100)]),
+            HashMap::from([
+                ("failing".to_string(), info.clone()),
+                ("good".to_string(), info),
+            ]),
+            HashMap::from([("failing".to_string(), 900), ("good".to_string(), 900)]),
+            HashMap::from([(
+                "failing".to_string(),
+                StaleSource {
+                    expired: 1100,
+                    last_update: 1000,
+                },
+            )]),
+        )
+    }
+
+    #[test]
+    fn failed_sources_keep_their_last_good_update_when_purged() {
+        let (mut metrics, mut expiration, mut payload, mut first_seen, mut stale) = failed_source();
+        metrics.get_mut("good").unwrap().expiration = 3600;
+
+        purge_expired(
+            &mut metrics,
+            &mut expiration,
+            &mut payload,
+            &mut first_seen,
+            &mut stale,
+            1200,
+        );
+
+        assert!(!metrics.contains_key("failing"));
+        assert!(metrics.contains_key("good"));
+        let failing = stale.get("failing").unwrap();
+        assert_eq!(failing.last_update, 1000);
+        assert_eq!(failing.expired, 1100);
+        assert!(!stale.contains_key("good"));
+    }
+
+    #[test]
+    fn failed_sources_are_shown_in_the_source_list() {
+        let (metrics, expiration, payload, _, stale) = failed_source();
+
+        let reply: serde_json::Value = serde_json::from_str(
+            &build_sources_reply(&metrics, &expiration, &payload, &stale, 1110).unwrap(),
+        )
+        .unwrap();
+        let failing = &reply["data"][0];
+        assert_eq!(failing["name"], "failing");
+        assert_eq!(failing["failed"], true);
+        assert_eq!(failing["last_success"], 1000);
+        assert_eq!(failing["last_update"], 1100);
+        assert_eq!(failing["purge_in"], 50);
+        let good = &reply["data"][1];
+        assert_eq!(good["name"], "good");
+        assert_eq!(good["failed"], false);
+        assert_eq!(good["last_success"], 1100);
     }
 }
@@ -2,7 +2,7 @@ use crate::constants;
 
 use lazy_static::lazy_static;
 use log::error;
-use prometheus::{Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntGaugeVec, Opts, Registry, TextEncoder};
 
 lazy_static! {
     pub static ref REGISTRY: Registry = Registry::new();
@@ -47,6 +47,38 @@ lazy_static! {
         .buckets(constants::METRICS_PAYLOAD_PARSE_TIME_BUCKETS.to_vec())
     )
     .unwrap();
+    pub static ref SOURCE_UP: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            constants::METRICS_SOURCE_UP_NAME,
+            constants::METRICS_SOURCE_UP_HELP
+        ),
+        &["source"],
+    )
+    .unwrap();
+    pub static ref SOURCE_LAST_UPDATE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            constants::METRICS_SOURCE_LAST_UPDATE_NAME,
+            constants::METRICS_SOURCE_LAST_UPDATE_HELP
+        ),
+        &["source"],
+    )
+    .unwrap();
+    pub static ref METADATA_CONFLICTS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            constants::METRICS_METADATA_CONFLICTS_NAME,
+            constants::METRICS_METADATA_CONFLICTS_HELP
+        ),
+        &["metric_name", "field"],
+    )
+    .unwrap();
+    pub static ref SOURCE_AGE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            constants::METRICS_SOURCE_AGE_NAME,
+            constants::METRICS_SOURCE_AGE_HELP
+        ),
+        &["source"],
+    )
+    .unwrap();
 }
 
 pub fn register() {
@@ -71,6 +103,14 @@ pub fn register() {
     REGISTRY
         .register(Box::new(PAYLOAD_PARSE_TIME.clone()))
         .unwrap();
+    REGISTRY.register(Box::new(SOURCE_UP.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(SOURCE_LAST_UPDATE.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(SOURCE_AGE.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(METADATA_CONFLICTS.clone()))
+        .unwrap();
 }
 
 pub fn metrics() -> String {
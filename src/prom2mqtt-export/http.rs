@@ -2,48 +2,93 @@ use crate::config;
 use crate::constants;
 use crate::data;
 use crate::exporter;
+use crate::reload;
 use log::{debug, error, info};
-use simple_error::bail;
 use std::error::Error;
-use std::sync::mpsc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::time;
 
 pub fn run(
     cfg: &config::Configuration,
     data_request: mpsc::Sender<data::Data>,
     data_reply: mpsc::Receiver<String>,
+    health: Arc<global::health::Health>,
+    reload_request: mpsc::Sender<reload::Request>,
+    reload: mpsc::Receiver<config::Configuration>,
+    stop: Arc<AtomicBool>,
 ) -> Result<(), Box<dyn Error>> {
-    let headers: Vec<tiny_http::Header> = vec![
-        tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
-        tiny_http::Header::from_bytes(&b"X-Clacks-Overhead"[..], &b"GNU Terry Pratchett"[..])
-            .unwrap(),
-    ];
+    let headers: Vec<tiny_http::Header> =
+        vec![
+            tiny_http::Header::from_bytes(&b"X-Clacks-Overhead"[..], &b"GNU Terry Pratchett"[..])
+                .unwrap(),
+        ];
 
-    let server = match tiny_http::Server::http(&cfg.prometheus.listen) {
-        Ok(v) => v,
-        Err(e) => bail!("cant start HTTP server - {}", e),
-    };
+    let server = global::web::server(&cfg.prometheus.listen, &cfg.web)?;
+    let mut auth = global::web::Authenticator::new(&cfg.web);
     info!(
-        "listening on http://{}{} for prometheus metric scrapes",
-        cfg.prometheus.listen, cfg.prometheus.path
+        "listening on {}://{}{} for prometheus metric scrapes",
+        global::web::scheme(&cfg.web),
+        cfg.prometheus.listen,
+        cfg.prometheus.path
     );
 
-    let mpath = &cfg.prometheus.path;
+    let mut mpath = cfg.prometheus.path.clone();
+    let one_second = time::Duration::from_secs(1);
 
     loop {
-        let request = match server.recv() {
-            Ok(v) => v,
+        if global::shutdown::requested(&stop) {
+            info!("stopping HTTP server");
+            return Ok(());
+        }
+
+        // don't block forever, a shutdown request must be noticed
+        let request = match server.recv_timeout(one_second) {
+            Ok(Some(v)) => v,
+            Ok(None) => continue,
             Err(e) => {
                 error!("can't process incoming HTTP  request: {}", e);
                 continue;
             }
         };
+        if let Ok(v) = reload.try_recv() {
+            auth = global::web::Authenticator::new(&v.web);
+            mpath = v.prometheus.path;
+        }
+
         let method = request.method();
         let url = request.url();
         let status_code: tiny_http::StatusCode;
         let mut payload: String;
-        let http_header = headers.clone();
+        let mut content_type = &b"text/plain"[..];
+        let mut http_header = headers.clone();
 
-        if method == &tiny_http::Method::Get {
+        if !auth.authorized(&request) {
+            status_code = tiny_http::StatusCode::from(401_i16);
+            payload = constants::HTTP_UNAUTHORIZED.to_string();
+            http_header.push(global::web::unauthorized_header());
+        } else if url == global::constants::HTTP_RELOAD_PATH {
+            if method == &tiny_http::Method::Post || method == &tiny_http::Method::Put {
+                debug!("sending reload request");
+                let (reply_send, reply_recv) = mpsc::channel::<Result<(), String>>();
+                reload_request.send(reload::Request {
+                    reply: Some(reply_send),
+                })?;
+                match reply_recv.recv()? {
+                    Ok(_) => {
+                        status_code = tiny_http::StatusCode::from(200_i16);
+                        payload = global::constants::HTTP_RELOADED.to_string();
+                    }
+                    Err(e) => {
+                        status_code = tiny_http::StatusCode::from(500_i16);
+                        payload = format!("{}\n", e);
+                    }
+                };
+            } else {
+                status_code = tiny_http::StatusCode::from(405_i16);
+                payload = constants::HTTP_METHOD_NOT_ALLOWED.to_string();
+            }
+        } else if method == &tiny_http::Method::Get {
             if url == "/" {
                 status_code = tiny_http::StatusCode::from(302_i16);
                 payload = constants::HTML_ROOT.to_string();
@@ -56,6 +101,37 @@ pub fn run(
                 debug!("waiting fore reply from data channel");
                 payload = data_reply.recv()?;
                 payload.push_str(&exporter::metrics());
+            } else if url == constants::API_SOURCES_PATH {
+                debug!("sending source list request");
+                let (reply_send, reply_recv) = mpsc::channel::<Result<String, String>>();
+                data_request.send(data::Data::SourcesRequest(reply_send))?;
+
+                debug!("waiting fore reply from data channel");
+                match reply_recv.recv()? {
+                    Ok(v) => {
+                        status_code = tiny_http::StatusCode::from(200_i16);
+                        content_type = &b"application/json"[..];
+                        payload = v;
+                    }
+                    Err(e) => {
+                        status_code = tiny_http::StatusCode::from(500_i16);
+                        payload = format!("can't build source list - {}\n", e);
+                    }
+                };
+            } else if url == global::constants::HTTP_HEALTHY_PATH {
+                status_code = tiny_http::StatusCode::from(200_i16);
+                payload = global::constants::HTTP_HEALTHY.to_string();
+            } else if url == global::constants::HTTP_READY_PATH {
+                if !health.is_connected() {
+                    status_code = tiny_http::StatusCode::from(503_i16);
+                    payload = global::constants::HTTP_NOT_CONNECTED.to_string();
+                } else if !health.is_operational() {
+                    status_code = tiny_http::StatusCode::from(503_i16);
+                    payload = constants::HTTP_NOT_SUBSCRIBED.to_string();
+                } else {
+                    status_code = tiny_http::StatusCode::from(200_i16);
+                    payload = global::constants::HTTP_READY.to_string();
+                }
             } else {
                 status_code = tiny_http::StatusCode::from(404_i16);
                 payload = constants::HTTP_NOT_FOUND.to_string();
@@ -65,6 +141,9 @@ pub fn run(
             payload = constants::HTTP_METHOD_NOT_ALLOWED.to_string();
         }
 
+        http_header
+            .push(tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type).unwrap());
+
         if let Err(e) = request.respond(tiny_http::Response::new(
             status_code,
             http_header,
@@ -1,23 +1,36 @@
 mod config;
+mod conflict;
 mod constants;
 mod data;
 mod exporter;
 mod http;
 mod mqtt_sub;
+mod reload;
+mod state;
 mod usage;
 
 use getopts::Options;
 use log::{debug, error, info};
-use std::sync::mpsc;
-use std::thread;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
 use std::{env, process};
+use std::{thread, time};
 
 fn main() {
     let argv: Vec<String> = env::args().collect();
     let mut options = Options::new();
     let mut log_level = log::LevelFilter::Info;
+    let mut cli_log_level: Option<log::LevelFilter> = None;
+    let mut cli_module_levels: Vec<(String, String)> = Vec::new();
 
     options.optflag("C", "check", "Check configuration file and exit");
+    options.optflag(
+        "",
+        "connect",
+        "Also check the connection to the MQTT broker when checking the configuration",
+    );
     options.optflag("D", "debug", "Enable debug logs");
     options.optflag("V", "version", "Show version information");
     options.optflag("h", "help", "Show help text");
@@ -27,6 +40,12 @@ fn main() {
         "Configuration file",
         constants::DEFAULT_CONFIG_FILE,
     );
+    options.optmulti(
+        "L",
+        "log-level",
+        "Set log level of a module",
+        "<module>=<level>",
+    );
     options.optflag("q", "quiet", "Quiet operation");
 
     let opts = match options.parse(&argv[1..]) {
@@ -51,12 +70,33 @@ fn main() {
 
     if opts.opt_present("D") {
         log_level = log::LevelFilter::Debug;
+        cli_log_level = Some(log_level);
     };
 
     if opts.opt_present("q") {
         log_level = log::LevelFilter::Warn;
+        cli_log_level = Some(log_level);
     };
 
+    for l in opts.opt_strs("L") {
+        match global::logging::parse_module_level(&l) {
+            Ok(v) => cli_module_levels.push(v),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                println!();
+                usage::show_usage();
+                process::exit(1);
+            }
+        };
+    }
+
+    if opts.opt_present("connect") && !opts.opt_present("C") {
+        eprintln!("Error: --connect can only be used together with --check");
+        println!();
+        usage::show_usage();
+        process::exit(1);
+    }
+
     let config_file = match opts.opt_str("c") {
         Some(v) => v,
         None => constants::DEFAULT_CONFIG_FILE.to_string(),
@@ -76,48 +116,147 @@ fn main() {
 
     if opts.opt_present("C") {
         info!("valid configuration file");
+        if opts.opt_present("connect") {
+            let mut report = global::check::Report::new();
+            global::check::mqtt(&configuration.mqtt, false, &mut report);
+            report.print();
+            if !report.passed() {
+                process::exit(1);
+            }
+        }
         process::exit(0);
     }
 
+    if let Err(e) = global::logging::configure(
+        &configuration.logging,
+        env!("CARGO_CRATE_NAME"),
+        cli_log_level,
+        &cli_module_levels,
+    ) {
+        error!("can't configure logging: {}", e);
+        process::exit(1);
+    }
+
     debug!("registering internal metrics");
     exporter::register();
 
     let (data_send, data_recv) = mpsc::channel::<data::Data>();
     let (http_send, http_recv) = mpsc::channel::<String>();
 
+    let health = Arc::new(global::health::Health::new());
+    let stop = Arc::new(AtomicBool::new(false));
+
+    if let Err(e) = global::shutdown::handle_signals(stop.clone()) {
+        error!("can't install signal handler: {}", e);
+        process::exit(1);
+    }
+
     // Spawn threads
+    let cfg = configuration.clone();
+    let data_stop = stop.clone();
     let data_thread_id = thread::spawn(move || {
-        match data::handler(data_recv, http_send) {
-            Ok(_) => {
-                process::exit(0);
-            }
-            Err(e) => {
-                panic!("{}", e);
-            }
-        };
+        let result = data::handler(&cfg, data_recv, http_send);
+        global::shutdown::worker_result(&data_stop, result)
     });
-    let dsc = data_send.clone();
+
+    if !configuration.state.file.is_empty() {
+        debug!(
+            "saving state to {} every {} seconds",
+            configuration.state.file, configuration.state.interval
+        );
+        let dsc = data_send.clone();
+        let interval = time::Duration::from_secs(configuration.state.interval);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if dsc.send(data::Data::SaveState).is_err() {
+                break;
+            }
+        });
+    }
+
+    let (reload_send, reload_receive) = mpsc::channel::<reload::Request>();
+    let (mqtt_reload_send, mqtt_reload_receive) = mpsc::channel::<global::mqtt::MQTT>();
+    let (http_reload_send, http_reload_receive) = mpsc::channel::<config::Configuration>();
+
+    debug!("spawning configuration reload thread");
     let cfg = configuration.clone();
-    let mqtt_thread_id = thread::spawn(move || match mqtt_sub::run(&cfg, dsc) {
-        Ok(_) => {
-            process::exit(0);
+    let dsc = data_send.clone();
+    thread::spawn(move || {
+        // only fails if the other threads are gone
+        if let Err(e) = reload::run(
+            &config_file,
+            cfg,
+            reload_receive,
+            dsc,
+            mqtt_reload_send,
+            http_reload_send,
+        ) {
+            debug!("configuration reload thread stopped: {}", e);
         }
+    });
+
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(v) => v,
         Err(e) => {
-            panic!("{}", e);
+            error!("can't install signal handler: {}", e);
+            process::exit(1);
         }
-    });
-    let http_thread_id = thread::spawn(move || {
-        match http::run(&configuration, data_send.clone(), http_recv) {
-            Ok(_) => {
-                process::exit(0);
+    };
+    let rsc = reload_send.clone();
+    thread::spawn(move || {
+        for sig in signals.forever() {
+            info!("signal {} received, reloading configuration", sig);
+            // allow log rotation without restart
+            if let Err(e) = global::logging::reopen() {
+                error!("can't reopen log file: {}", e);
             }
-            Err(e) => {
-                panic!("{}", e);
+            if let Err(e) = rsc.send(reload::Request { reply: None }) {
+                error!("can't send reload request: {}", e);
             }
-        };
+        }
     });
 
-    data_thread_id.join().unwrap();
-    http_thread_id.join().unwrap();
-    mqtt_thread_id.join().unwrap();
+    let dsc = data_send.clone();
+    let cfg = configuration.clone();
+    let mqtt_health = health.clone();
+    let mqtt_stop = stop.clone();
+    let mqtt_thread_id = thread::spawn(move || {
+        let result = mqtt_sub::run(
+            &cfg,
+            dsc,
+            mqtt_health,
+            mqtt_reload_receive,
+            mqtt_stop.clone(),
+        );
+        global::shutdown::worker_result(&mqtt_stop, result)
+    });
+
+    let dsc = data_send.clone();
+    let http_stop = stop.clone();
+    let http_thread_id = thread::spawn(move || {
+        let result = http::run(
+            &configuration,
+            dsc,
+            http_recv,
+            health,
+            reload_send,
+            http_reload_receive,
+            http_stop.clone(),
+        );
+        global::shutdown::worker_result(&http_stop, result)
+    });
+
+    // Stop receiving new data first, the data handler saves the state after all other threads are gone
+    let mut ok = global::shutdown::join("MQTT", mqtt_thread_id);
+    ok &= global::shutdown::join("HTTP server", http_thread_id);
+
+    if data_send.send(data::Data::Shutdown).is_err() {
+        debug!("data handler already stopped");
+    }
+    ok &= global::shutdown::join("data handler", data_thread_id);
+
+    if !ok {
+        process::exit(1);
+    }
+    info!("shutdown complete");
 }
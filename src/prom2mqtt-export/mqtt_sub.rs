@@ -4,39 +4,164 @@ use crate::data;
 use log::{debug, error, info, warn};
 use simple_error::bail;
 use std::error::Error;
-use std::sync::mpsc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
 use std::{thread, time};
 
 pub fn run(
     cfg: &config::Configuration,
     data_sender: mpsc::Sender<data::Data>,
+    health: Arc<global::health::Health>,
+    reload: mpsc::Receiver<global::mqtt::MQTT>,
+    stop: Arc<AtomicBool>,
 ) -> Result<(), Box<dyn Error>> {
     let one_second = time::Duration::from_secs(1);
-    let conn = global::mqtt::connection_builder(&cfg.mqtt)?;
-    let client = global::mqtt::client_builder(&cfg.mqtt)?;
+    let mut mqtt_cfg = cfg.mqtt.clone();
+    let mut client = match connect(&mqtt_cfg, &health, &stop)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    let mut messages = client.start_consuming();
+
+    loop {
+        if global::shutdown::requested(&stop) {
+            break;
+        }
+
+        if let Ok(v) = reload.try_recv() {
+            if global::mqtt::connection_changed(&mqtt_cfg, &v) {
+                // the current connection is only replaced if the new broker can be reached
+                info!(
+                    "MQTT connection settings changed, connecting to {}",
+                    v.broker
+                );
+                let new_client = match connect(&v, &health, &stop) {
+                    Ok(Some(c)) => c,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!(broker = v.broker.as_str();
+                            "can't connect to MQTT broker {}, keeping the connection to {} - {}",
+                            v.broker,
+                            mqtt_cfg.broker,
+                            e
+                        );
+                        health.set_connected(client.is_connected());
+                        health.set_operational(client.is_connected());
+                        continue;
+                    }
+                };
+                info!("disconnecting from MQTT broker {}", mqtt_cfg.broker);
+                client.stop_consuming();
+                if let Err(e) = client.disconnect(None) {
+                    warn!("can't disconnect from MQTT broker - {}", e);
+                }
+                client = new_client;
+                messages = client.start_consuming();
+            } else if v.topic != mqtt_cfg.topic || v.qos != mqtt_cfg.qos {
+                info!(
+                    "unsubscribing from topic {} on {}",
+                    mqtt_cfg.topic, mqtt_cfg.broker
+                );
+                if let Err(e) = client.unsubscribe(&mqtt_cfg.topic) {
+                    warn!("can't unsubscribe from topic {} - {}", mqtt_cfg.topic, e);
+                }
+                info!(
+                    "subscribing to topic {} on {} qith QoS {}",
+                    v.topic, v.broker, v.qos
+                );
+                if let Err(e) = client.subscribe(&v.topic, v.qos) {
+                    bail!("can't subscribe to topic {} - {}", v.topic, e);
+                }
+            }
+            mqtt_cfg = v;
+        }
+
+        // don't block forever, configuration changes must be picked up
+        let msg = match messages.recv_timeout(one_second) {
+            Ok(v) => v,
+            Err(e) if e.is_timeout() => continue,
+            Err(_) => break,
+        };
+
+        match msg {
+            Some(vmsg) => {
+                if global::check::is_test_topic(vmsg.topic()) {
+                    debug!("ignoring test message on {}", vmsg.topic());
+                    continue;
+                }
+                info!(topic = vmsg.topic(), qos = vmsg.qos(), bytes = vmsg.payload().len();
+                    "received data on {} with qos {}", vmsg.topic(), vmsg.qos()
+                );
+                let (pdata, pinfo) = match data::parse_raw_metrics(vmsg.payload().to_vec()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("can't parse received data - {}", e);
+                        continue;
+                    }
+                };
+                debug!("sending parsed data to data handler");
+                data_sender.send(data::Data::MetricData(pdata, pinfo))?;
+            }
+            None => {
+                if !client.is_connected() {
+                    warn!("connection to broker was lost, reconnecting");
+                    health.set_connected(false);
+                    health.set_operational(false);
+
+                    let rstatus = client.reconnect()?;
+                    health.set_connected(true);
+                    subscribe(&mqtt_cfg, &client, &rstatus)?;
+                    health.set_operational(true);
+                }
+            }
+        }
+    }
+
+    // a clean disconnect ends the session at the broker immediately
+    info!("disconnecting from MQTT broker {}", mqtt_cfg.broker);
+    client.stop_consuming();
+    if let Err(e) = client.disconnect(None) {
+        warn!("can't disconnect from MQTT broker - {}", e);
+    }
+    health.set_connected(false);
+    health.set_operational(false);
+
+    Ok(())
+}
+
+// Returns None if a shutdown was requested while connecting
+fn connect(
+    cfg: &global::mqtt::MQTT,
+    health: &global::health::Health,
+    stop: &AtomicBool,
+) -> Result<Option<paho_mqtt::Client>, Box<dyn Error>> {
+    let one_second = time::Duration::from_secs(1);
+    let conn = global::mqtt::connection_builder(cfg)?;
+    let client = global::mqtt::client_builder(cfg)?;
     let cstatus: paho_mqtt::ServerResponse;
 
+    info!(broker = cfg.broker.as_str(); "connecting to MQTT broker {}", cfg.broker);
     let mut ticktock: u64 = 0;
     loop {
         let mco = conn.clone();
         cstatus = match client.connect(mco) {
             Err(e) => {
-                error!(
-                    "connection to MQTT broker {} failed: {}",
-                    cfg.mqtt.broker, e
-                );
-                if ticktock > cfg.mqtt.reconnect_timeout {
+                error!(broker = cfg.broker.as_str(); "connection to MQTT broker {} failed: {}", cfg.broker, e);
+                if ticktock > cfg.reconnect_timeout {
                     error!(
                         "reconnect timer ({} seconds) exceeds reconnect_timeout ({} seconds)",
-                        ticktock, cfg.mqtt.reconnect_timeout
+                        ticktock, cfg.reconnect_timeout
                     );
                     return Err(Box::new(e));
                 }
+                if global::shutdown::requested(stop) {
+                    return Ok(None);
+                }
                 thread::sleep(one_second);
                 ticktock += 1;
                 warn!(
                     "retrying to connect to MQTT broker {} - attempt {}/{}",
-                    cfg.mqtt.broker, ticktock, cfg.mqtt.reconnect_timeout
+                    cfg.broker, ticktock, cfg.reconnect_timeout
                 );
                 continue;
             }
@@ -45,44 +170,32 @@ pub fn run(
         break;
     }
 
+    info!(broker = cfg.broker.as_str(); "connected to MQTT broker {}", cfg.broker);
+    health.set_connected(true);
+    subscribe(cfg, &client, &cstatus)?;
+    health.set_operational(true);
+
+    Ok(Some(client))
+}
+
+fn subscribe(
+    cfg: &global::mqtt::MQTT,
+    client: &paho_mqtt::Client,
+    cstatus: &paho_mqtt::ServerResponse,
+) -> Result<(), Box<dyn Error>> {
     if let Some(v) = cstatus.connect_response() {
+        // the broker still knows about our subscription if the session is present
         if !v.session_present {
             info!(
                 "subscribing to topic {} on {} qith QoS {}",
-                cfg.mqtt.topic, cfg.mqtt.broker, cfg.mqtt.qos
+                cfg.topic, cfg.broker, cfg.qos
             );
-            if let Err(e) = client.subscribe(&cfg.mqtt.topic, cfg.mqtt.qos) {
-                bail!("can't subscribe to topic {} - {}", cfg.mqtt.topic, e);
+            if let Err(e) = client.subscribe(&cfg.topic, cfg.qos) {
+                bail!("can't subscribe to topic {} - {}", cfg.topic, e);
             }
         }
     } else {
         bail!("empty connect_response result from MQTT connection");
     };
-
-    let messages = client.start_consuming();
-
-    for msg in messages.iter() {
-        match msg {
-            Some(vmsg) => {
-                info!("received data on {} with qos {}", vmsg.topic(), vmsg.qos());
-                let pdata = match data::parse_raw_metrics(vmsg.payload().to_vec()) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!("can't parse received data - {}", e);
-                        continue;
-                    }
-                };
-                debug!("sending parsed data to data handler");
-                data_sender.send(data::Data::MetricData(pdata))?;
-            }
-            None => {
-                if !client.is_connected() {
-                    warn!("connection to broker was lost, reconnecting");
-                    client.reconnect()?;
-                }
-            }
-        }
-    }
-
     Ok(())
 }
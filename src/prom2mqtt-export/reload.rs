@@ -0,0 +1,63 @@
+use crate::config;
+use crate::data;
+
+use log::{error, info, warn};
+use std::error::Error;
+use std::sync::mpsc;
+
+pub struct Request {
+    // HTTP requests wait for the result, signals don't
+    pub reply: Option<mpsc::Sender<Result<(), String>>>,
+}
+
+pub fn run(
+    config_file: &str,
+    cfg: config::Configuration,
+    requests: mpsc::Receiver<Request>,
+    data_reload: mpsc::Sender<data::Data>,
+    mqtt_reload: mpsc::Sender<global::mqtt::MQTT>,
+    http_reload: mpsc::Sender<config::Configuration>,
+) -> Result<(), Box<dyn Error>> {
+    let mut current = cfg;
+
+    loop {
+        let request = requests.recv()?;
+        info!("reloading configuration from {}", config_file);
+
+        let result = match config::parse_config_file(config_file) {
+            Ok(v) => {
+                if v.prometheus.listen != current.prometheus.listen
+                    || v.web.tls_server_config != current.web.tls_server_config
+                {
+                    warn!(
+                        "changes of the listen address or the TLS configuration require a restart"
+                    );
+                }
+                if v.logging != current.logging {
+                    warn!("changes of the logging configuration require a restart");
+                }
+                if v.state.interval != current.state.interval {
+                    warn!("changes of the state save interval require a restart");
+                }
+                data_reload.send(data::Data::Reload(Box::new(v.clone())))?;
+                mqtt_reload.send(v.mqtt.clone())?;
+                http_reload.send(v.clone())?;
+                current = v;
+                info!("configuration reloaded from {}", config_file);
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "can't reload configuration from {}, keeping current configuration - {}",
+                    config_file, e
+                );
+                Err(e.to_string())
+            }
+        };
+
+        if let Some(reply) = request.reply {
+            // the HTTP client may have gone away in the meantime
+            let _ = reply.send(result);
+        }
+    }
+}
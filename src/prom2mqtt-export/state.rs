@@ -0,0 +1,199 @@
+use crate::data;
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+pub struct State {
+    pub sources: Vec<Source>,
+    #[serde(default)]
+    pub stale: HashMap<String, data::StaleSource>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Source {
+    pub compressed: bool,
+    // missing in state files written before first_seen was tracked, load() uses last_update instead
+    #[serde(default)]
+    pub first_seen: i64,
+    pub last_update: i64,
+    pub message: global::payload::Message,
+    pub payload_size: usize,
+}
+
+// Borrowed counterpart of State/Source, avoids copying all metric data for every snapshot
+#[derive(Serialize)]
+struct StateRef<'a> {
+    sources: Vec<SourceRef<'a>>,
+    stale: &'a HashMap<String, data::StaleSource>,
+}
+
+#[derive(Serialize)]
+struct SourceRef<'a> {
+    compressed: bool,
+    first_seen: i64,
+    last_update: i64,
+    message: &'a global::payload::Message,
+    payload_size: usize,
+}
+
+pub fn load(f: &str, now: i64) -> Result<State, Box<dyn Error>> {
+    let raw = fs::read_to_string(f)?;
+    let mut parsed: State = serde_json::from_str(&raw)?;
+    let total = parsed.sources.len();
+
+    for s in parsed.sources.iter_mut() {
+        if s.first_seen == 0 {
+            s.first_seen = s.last_update;
+        }
+    }
+
+    let mut expired: Vec<(String, data::StaleSource)> = Vec::new();
+    parsed.sources.retain(|s| {
+        if now - s.last_update >= s.message.expiration {
+            debug!(
+                "'{}' from state file was last updated {} - {} seconds ago, expiration set to {}, keeping it as stale source",
+                s.message.name,
+                s.last_update,
+                now - s.last_update,
+                s.message.expiration
+            );
+            expired.push((
+                s.message.name.clone(),
+                data::StaleSource {
+                    expired: s.last_update + s.message.expiration,
+                    last_update: s.last_update,
+                },
+            ));
+            return false;
+        }
+        true
+    });
+    // sources already known as stale keep the time of their last good data
+    for (name, stale) in expired {
+        parsed.stale.entry(name).or_insert(stale);
+    }
+
+    info!(
+        "loaded {} sources from state file {}, {} already expired",
+        parsed.sources.len(),
+        f,
+        total - parsed.sources.len()
+    );
+    Ok(parsed)
+}
+
+pub fn save(
+    f: &str,
+    metrics: &HashMap<String, global::payload::Message>,
+    metrics_expiration: &HashMap<String, i64>,
+    metrics_payload: &HashMap<String, data::PayloadInfo>,
+    metrics_first_seen: &HashMap<String, i64>,
+    stale_sources: &HashMap<String, data::StaleSource>,
+) -> Result<(), Box<dyn Error>> {
+    let mut state = StateRef {
+        sources: Vec::new(),
+        stale: stale_sources,
+    };
+
+    for (name, message) in metrics.iter() {
+        let last_update = match metrics_expiration.get(name) {
+            Some(v) => *v,
+            None => continue,
+        };
+        let (payload_size, compressed) = match metrics_payload.get(name) {
+            Some(v) => (v.size, v.compressed),
+            None => (0, false),
+        };
+        state.sources.push(SourceRef {
+            compressed,
+            first_seen: *metrics_first_seen.get(name).unwrap_or(&last_update),
+            last_update,
+            message,
+            payload_size,
+        });
+    }
+
+    // Write to a temporary file first, so an interrupted write never leaves a truncated state file
+    let tmp = format!("{}.tmp", f);
+    fs::write(&tmp, serde_json::to_string(&state)?)?;
+    fs::rename(&tmp, Path::new(f))?;
+
+    info!("saved {} sources to state file {}", state.sources.len(), f);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_state(name: &str, content: &str) -> String {
+        let f = std::env::temp_dir()
+            .join(format!("{}-{}.state", name, std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        fs::write(&f, content).unwrap();
+        f
+    }
+
+    #[test]
+    fn expired_sources_are_restored_as_stale() {
+        let f = write_state(
+            "expired",
+            r#"{"sources":[
+                {"compressed":false,"first_seen":100,"last_update":1000,"payload_size":10,
+                 "message":{"expiration":60,"name":"old","payload":[]}},
+                {"compressed":true,"first_seen":100,"last_update":1950,"payload_size":10,
+                 "message":{"expiration":60,"name":"current","payload":[]}}
+            ],"stale":{}}"#,
+        );
+        let state = load(&f, 2000).unwrap();
+        fs::remove_file(&f).unwrap();
+
+        assert_eq!(state.sources.len(), 1);
+        assert_eq!(state.sources[0].message.name, "current");
+        let stale = state.stale.get("old").unwrap();
+        assert_eq!(stale.last_update, 1000);
+        assert_eq!(stale.expired, 1060);
+    }
+
+    #[test]
+    fn state_without_first_seen_is_loaded() {
+        // state file written before first_seen has been added
+        let f = write_state(
+            "pre029",
+            r#"{"sources":[
+                {"compressed":false,"last_update":1950,"payload_size":42,
+                 "message":{"expiration":60,"name":"node1","payload":[
+                    {"data":["up_time 1"],"data_type":"gauge","help":"Uptime","metric_name":"up_time"}]}}
+            ],"stale":{"node2":{"expired":1500,"last_update":1400}}}"#,
+        );
+        let state = load(&f, 2000).unwrap();
+        fs::remove_file(&f).unwrap();
+
+        assert_eq!(state.sources.len(), 1);
+        assert_eq!(state.sources[0].first_seen, 1950);
+        assert_eq!(state.sources[0].message.payload[0].data, vec!["up_time 1"]);
+        assert_eq!(state.stale.get("node2").unwrap().last_update, 1400);
+    }
+
+    #[test]
+    fn known_stale_sources_keep_their_last_update() {
+        let f = write_state(
+            "known",
+            r#"{"sources":[
+                {"compressed":false,"first_seen":100,"last_update":1000,"payload_size":10,
+                 "message":{"expiration":60,"name":"failed","payload":[]}}
+            ],"stale":{"failed":{"expired":1000,"last_update":500}}}"#,
+        );
+        let state = load(&f, 2000).unwrap();
+        fs::remove_file(&f).unwrap();
+
+        assert!(state.sources.is_empty());
+        assert_eq!(state.stale.get("failed").unwrap().last_update, 500);
+    }
+}
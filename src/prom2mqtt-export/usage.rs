@@ -3,7 +3,7 @@ use crate::constants;
 pub fn show_usage() {
     global::usage::show_version();
     println!(
-        "Usage: {} [-c <cfg>|--config=<cfg>] [-h|--help] [-q|--quiet] [-C|--check] [-D|--debug] [-V|--version]
+        "Usage: {} [-c <cfg>|--config=<cfg>] [-h|--help] [-q|--quiet] [-C|--check [--connect]] [-D|--debug] [-L <module>=<level>|--log-level=<module>=<level>] [-V|--version]
 
     -c <cfg>        Read configuration from file <cfg>
     --config=<cfg>  Default: {}
@@ -17,9 +17,15 @@ pub fn show_usage() {
     -C              Check configuration file and exit
     --check
 
+    --connect       Together with --check: connect to the MQTT broker, subscribe to the topic
+                    and print a report. Exit with a non-zero exit code if any check failed
+
     -D              Enable debug log
     --debug
 
+    -L <module>=<level>             Set log level of <module> to <level>, can be repeated
+    --log-level=<module>=<level>    e.g. -L mqtt_sub=debug -L data=info
+
     -V              Show version information
     --version
 
@@ -1,7 +1,47 @@
 pub const DEFAULT_CONFIG_FILE: &str = "/etc/prometheus-mqtt-transport/fetch.yaml";
 pub const DEFAULT_INTERVAL: i64 = 60;
 pub const DEFAULT_SCRAPE_TIMEOUT: u64 = 10;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const DEFAULT_AUTHORIZATION_TYPE: &str = "Bearer";
+pub const DEFAULT_FILE_SD_REFRESH_INTERVAL: u64 = 5;
+pub const DEFAULT_SCRAPE_METRICS_PATH: &str = "/metrics";
+pub const DEFAULT_SCRAPE_SCHEME: &str = "http";
+pub const DEFAULT_MAX_RESENDS: u32 = 3;
+pub const DEFAULT_RETRY_INITIAL_BACKOFF: f64 = 1.0;
+pub const DEFAULT_RETRY_MAX_BACKOFF: f64 = 30.0;
+pub const DEFAULT_RETRY_STATUS_CODES: [u16; 4] = [429, 502, 503, 504];
+pub const DEFAULT_RELABEL_REGEX: &str = "(.*)";
+pub const DEFAULT_RELABEL_REPLACEMENT: &str = "$1";
+pub const DEFAULT_RELABEL_SEPARATOR: &str = ";";
+pub const LABEL_ADDRESS: &str = "__address__";
+pub const LABEL_INSTANCE: &str = "instance";
+pub const LABEL_JOB: &str = "job";
+pub const LABEL_METRICS_PATH: &str = "__metrics_path__";
+pub const LABEL_PARAM_PREFIX: &str = "__param_";
+pub const LABEL_SCHEME: &str = "__scheme__";
 pub const SCRAPE_NAME_LABEL: &str = "prom2mqtt_fetch_scrape";
+pub const UNIX_SOCKET_URL_PREFIX: &str = "unix://";
+// the text format is preferred, OpenMetrics data is converted into the text format
+pub const SCRAPE_ACCEPT: &str = "text/plain;version=0.0.4;q=0.5,application/openmetrics-text;version=1.0.0;q=0.4,application/openmetrics-text;version=0.0.1;q=0.3,*/*;q=0.1";
+pub const SCRAPE_ACCEPT_ENCODING: &str = "gzip";
+// limits the memory used by compressed replies that expand to huge amounts of data
+pub const SCRAPE_MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+pub const CONTENT_TYPE_OPENMETRICS: &str = "application/openmetrics-text";
+pub const CONTENT_TYPE_TEXT: &str = "text/plain";
+pub const TEXTFILE_MTIME_METRIC: &str = "prom2mqtt_fetch_textfile_mtime_seconds";
+pub const TEXTFILE_ERROR_METRIC: &str = "prom2mqtt_fetch_textfile_scrape_error";
+pub const EXEC_POLL_INTERVAL_MS: u64 = 10;
+pub const SERVER_NAME_RESOLVE_INTERVAL: u64 = 300;
+pub const SYNTHETIC_UP_NAME: &str = "up";
+pub const SYNTHETIC_UP_HELP: &str = "1 if the scrape was successful, 0 otherwise";
+pub const SYNTHETIC_SCRAPE_DURATION_NAME: &str = "scrape_duration_seconds";
+pub const SYNTHETIC_SCRAPE_DURATION_HELP: &str = "Duration of the scrape in seconds";
+pub const SYNTHETIC_SCRAPE_SAMPLES_NAME: &str = "scrape_samples_scraped";
+pub const SYNTHETIC_SCRAPE_SAMPLES_HELP: &str = "Number of samples of the scrape";
+pub const SYNTHETIC_SCRAPE_BODY_SIZE_NAME: &str = "scrape_body_size_bytes";
+pub const SYNTHETIC_SCRAPE_BODY_SIZE_HELP: &str = "Size of the scraped data in bytes";
+pub const SYNTHETIC_SCRAPE_RETRIES_NAME: &str = "scrape_retries";
+pub const SYNTHETIC_SCRAPE_RETRIES_HELP: &str = "Number of retries of the scrape";
 pub const DEFAULT_PROMETHEUS_LISTEN: &str = "localhost:9998";
 pub const DEFAULT_PROMETHEUS_PATH: &str = "/metrics";
 
@@ -24,6 +64,11 @@ pub const METRIC_SCRAPE_DURATION_BUCKETS: &[f64; 46] = &[
 
 pub const METRIC_SCRAPE_SUCCESS_NAME: &str = "prom2mqtt_fetch_scrape_success";
 pub const METRIC_SCRAPE_SUCCESS_HELP: &str = "Success status of scrape";
+pub const METRIC_SCRAPE_RETRIES_NAME: &str = "prom2mqtt_fetch_scrape_retries_total";
+pub const METRIC_SCRAPE_RETRIES_HELP: &str = "Number of retries of failed scrapes";
+pub const METRIC_SCRAPE_HTTP_ERRORS_NAME: &str = "prom2mqtt_fetch_scrape_http_errors_total";
+pub const METRIC_SCRAPE_HTTP_ERRORS_HELP: &str =
+    "Number of scrape attempts failed with a HTTP status code other than 2xx";
 
 // Metrics for processing of scraped data
 pub const METRIC_COMPRESSION_NAME: &str = "prom2mqtt_fetch_compression";
@@ -56,6 +101,8 @@ pub const METRIC_MQTT_SEND_TIME_BUCKETS: &[f64; 21] = &[
 pub const METRIC_MQTT_SUCCESS_NAME: &str = "prom2mqtt_fetch_mqtt_send_success";
 pub const METRIC_MQTT_SUCCESS_HELP: &str = "Success status of MQTT message publishing";
 
-pub const HTML_ROOT: &str = "<html>\n<head><title>Prometheus MQTT transport - scraper</title></head>\n<body>\n<h1>Prometheus MQTT transport - scraper</h1>\n<p><a href=\"/metrics\">Metrics</a></p>\n</body>\n</html>\n";
+pub const HTML_ROOT: &str = "<html>\n<head><title>Prometheus MQTT transport - scraper</title></head>\n<body>\n<h1>Prometheus MQTT transport - scraper</h1>\n<p><a href=\"/metrics\">Metrics</a></p>\n<p><a href=\"/-/healthy\">Health</a></p>\n<p><a href=\"/-/ready\">Readiness</a></p>\n</body>\n</html>\n";
 pub const HTTP_NOT_FOUND: &str = "Not found";
 pub const HTTP_METHOD_NOT_ALLOWED: &str = "Method not allowed";
+pub const HTTP_UNAUTHORIZED: &str = "Unauthorized";
+pub const HTTP_NOT_PUBLISHED: &str = "No data published to MQTT broker yet\n";
@@ -0,0 +1,5 @@
+pub enum Data {
+    Payload(Vec<u8>),
+    Reload(global::mqtt::MQTT),
+    Shutdown,
+}
@@ -0,0 +1,421 @@
+use crate::config;
+use crate::constants;
+use crate::relabel;
+
+use log::{debug, error, info, warn};
+use simple_error::bail;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+use url::Url;
+
+// Expands scrape jobs with static_configs or service discovery into one scrape per target and keeps track of the target files
+pub struct Discovery {
+    // modification times of the target files found during the last refresh
+    files: HashMap<String, Option<SystemTime>>,
+    last_check: Instant,
+}
+
+impl Discovery {
+    pub fn new() -> Self {
+        Discovery {
+            files: HashMap::new(),
+            last_check: Instant::now(),
+        }
+    }
+
+    // Check if target files were added, removed or modified since the targets were built
+    pub fn changed(&mut self, cfg: &config::Configuration) -> bool {
+        let refresh = match refresh_interval(cfg) {
+            Some(v) => v,
+            None => return false,
+        };
+        if self.last_check.elapsed() < refresh {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let files = scan(cfg);
+        if files != self.files {
+            info!("target files of file based service discovery have been changed");
+            return true;
+        }
+        false
+    }
+
+    // Build the list of scrapes. If a target file can't be read, the targets read from it before are kept
+    pub fn targets(
+        &mut self,
+        cfg: &config::Configuration,
+        previous: &[config::Scrape],
+    ) -> Vec<config::Scrape> {
+        let mut targets: Vec<config::Scrape> = Vec::new();
+
+        self.files = scan(cfg);
+        self.last_check = Instant::now();
+
+        for job in cfg.scrape.iter() {
+            if job.file_sd_configs.is_empty() && job.static_configs.is_empty() {
+                targets.push(job.clone());
+                continue;
+            }
+
+            let relabeler = match relabel::Relabeler::new(&job.relabel_configs) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("'{}': invalid relabel_configs - {}", job.name, e);
+                    continue;
+                }
+            };
+
+            for group in job.static_configs.iter() {
+                for target in group.targets.iter() {
+                    if let Some(v) = build_target(job, &relabeler, target, &group.labels, "") {
+                        targets.push(v);
+                    }
+                }
+            }
+
+            for file in job_files(job) {
+                match read_target_file(&file) {
+                    Ok(groups) => {
+                        for group in groups.iter() {
+                            for target in group.targets.iter() {
+                                if let Some(v) =
+                                    build_target(job, &relabeler, target, &group.labels, &file)
+                                {
+                                    targets.push(v);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!(
+                            "'{}': can't read target file {}, keeping its current targets - {}",
+                            job.name, file, e
+                        );
+                        let prefix = format!("{}/", job.name);
+                        targets.extend(
+                            previous
+                                .iter()
+                                .filter(|s| s.sd_file == file && s.name.starts_with(&prefix))
+                                .cloned(),
+                        );
+                    }
+                };
+            }
+        }
+
+        let mut names: HashSet<String> = HashSet::new();
+        targets.retain(|s| {
+            if names.insert(s.name.clone()) {
+                true
+            } else {
+                warn!("'{}': duplicate target, ignoring it", s.name);
+                false
+            }
+        });
+
+        debug!("{} scrape targets", targets.len());
+        targets
+    }
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The shortest refresh interval of all file_sd_configs, None if file based service discovery isn't used
+fn refresh_interval(cfg: &config::Configuration) -> Option<Duration> {
+    cfg.scrape
+        .iter()
+        .flat_map(|s| s.file_sd_configs.iter())
+        .map(|f| f.refresh_interval)
+        .min()
+        .map(Duration::from_secs)
+}
+
+fn scan(cfg: &config::Configuration) -> HashMap<String, Option<SystemTime>> {
+    let mut files: HashMap<String, Option<SystemTime>> = HashMap::new();
+    for job in cfg.scrape.iter() {
+        for file in job_files(job) {
+            let mtime = match fs::metadata(&file) {
+                Ok(v) => v.modified().ok(),
+                Err(_) => None,
+            };
+            files.insert(file, mtime);
+        }
+    }
+    files
+}
+
+// All files matching the patterns of the file_sd_configs of a scrape job
+fn job_files(job: &config::Scrape) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for sd in job.file_sd_configs.iter() {
+        for pattern in sd.files.iter() {
+            let paths = match glob::glob(pattern) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("'{}': invalid file pattern {} - {}", job.name, pattern, e);
+                    continue;
+                }
+            };
+            for path in paths.flatten() {
+                let file = path.to_string_lossy().to_string();
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+    }
+    files
+}
+
+// Target files can be in JSON or YAML format
+fn read_target_file(file: &str) -> Result<Vec<config::TargetGroup>, Box<dyn Error>> {
+    let raw = fs::read_to_string(file)?;
+    let groups: Vec<config::TargetGroup> = if file.ends_with(".json") {
+        serde_json::from_str(&raw)?
+    } else {
+        serde_yaml::from_str(&raw)?
+    };
+
+    for group in groups.iter() {
+        for target in group.targets.iter() {
+            if target.is_empty() {
+                bail!("invalid target '{}'", target);
+            }
+        }
+    }
+    Ok(groups)
+}
+
+// Build the scrape of a single target. Like Prometheus, the target labels are relabeled before the URL
+// is built from the __scheme__, __address__, __metrics_path__ and __param_<name> labels.
+// Returns None if the target has been dropped by relabeling
+fn build_target(
+    job: &config::Scrape,
+    relabeler: &relabel::Relabeler,
+    target: &str,
+    group_labels: &HashMap<String, String>,
+    file: &str,
+) -> Option<config::Scrape> {
+    let name = format!("{}/{}", job.name, target);
+
+    let mut labels: HashMap<String, String> = HashMap::new();
+    labels.insert(constants::LABEL_ADDRESS.to_string(), target.to_string());
+    labels.insert(constants::LABEL_SCHEME.to_string(), job.scheme.clone());
+    labels.insert(
+        constants::LABEL_METRICS_PATH.to_string(),
+        job.metrics_path.clone(),
+    );
+    labels.insert(constants::LABEL_JOB.to_string(), job.name.clone());
+    for (k, v) in job.params.iter() {
+        if let Some(first) = v.first() {
+            labels.insert(
+                format!("{}{}", constants::LABEL_PARAM_PREFIX, k),
+                first.to_string(),
+            );
+        }
+    }
+    labels.extend(job.labels.clone());
+    labels.extend(group_labels.clone());
+
+    let mut labels = match relabeler.apply(labels) {
+        Some(v) => v,
+        None => {
+            debug!("'{}': target has been dropped by relabeling", name);
+            return None;
+        }
+    };
+
+    let address = labels
+        .get(constants::LABEL_ADDRESS)
+        .cloned()
+        .unwrap_or_default();
+    if address.is_empty() {
+        warn!(
+            "'{}': no address left after relabeling, ignoring target",
+            name
+        );
+        return None;
+    }
+    let scheme = labels
+        .get(constants::LABEL_SCHEME)
+        .cloned()
+        .unwrap_or_default();
+    let metrics_path = labels
+        .get(constants::LABEL_METRICS_PATH)
+        .cloned()
+        .unwrap_or_default();
+
+    // __param_<name> labels replace the first value of the configured parameter
+    let mut params: BTreeMap<String, Vec<String>> = job
+        .params
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    for (k, v) in labels.iter() {
+        if let Some(param) = k.strip_prefix(constants::LABEL_PARAM_PREFIX) {
+            let values = params.entry(param.to_string()).or_default();
+            if values.is_empty() {
+                values.push(v.clone());
+            } else {
+                values[0] = v.clone();
+            }
+        }
+    }
+
+    let mut url = match Url::parse(&format!("{}://{}{}", scheme, address, metrics_path)) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("'{}': invalid URL for target, ignoring it - {}", name, e);
+            return None;
+        }
+    };
+    if !params.is_empty() {
+        let mut query = url.query_pairs_mut();
+        for (k, values) in params.iter() {
+            for v in values.iter() {
+                query.append_pair(k, v);
+            }
+        }
+    }
+
+    if !labels.contains_key(constants::LABEL_INSTANCE) {
+        labels.insert(constants::LABEL_INSTANCE.to_string(), address);
+    }
+    // labels starting with __ are reserved for internal use
+    labels.retain(|k, _| !k.starts_with("__"));
+
+    let mut scrape = job.clone();
+    scrape.name = name;
+    scrape.url = url.to_string();
+    scrape.labels = labels;
+    scrape.file_sd_configs = Vec::new();
+    scrape.static_configs = Vec::new();
+    scrape.sd_file = file.to_string();
+
+    if !scrape.suppress_scrape_name {
+        scrape.labels.insert(
+            constants::SCRAPE_NAME_LABEL.to_string(),
+            scrape.name.clone(),
+        );
+    }
+    Some(scrape)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Expected<'a> = Option<(&'a str, Vec<(&'a str, &'a str)>)>;
+
+    fn job(yaml: &str) -> config::Scrape {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn relabeler(job: &config::Scrape) -> relabel::Relabeler {
+        relabel::Relabeler::new(&job.relabel_configs).unwrap()
+    }
+
+    #[test]
+    fn targets() {
+        let group: HashMap<String, String> =
+            HashMap::from([("env".to_string(), "prod".to_string())]);
+        // (job, target, expected URL and labels or None if the target is ignored)
+        let cases: Vec<(&str, &str, Expected)> = vec![
+            (
+                "name: node\n",
+                "host1:9100",
+                Some((
+                    "http://host1:9100/metrics",
+                    vec![
+                        ("instance", "host1:9100"),
+                        ("job", "node"),
+                        ("env", "prod"),
+                        ("prom2mqtt_fetch_scrape", "node/host1:9100"),
+                    ],
+                )),
+            ),
+            (
+                "name: node\nscheme: https\nmetrics_path: /stats\nsuppress_scrape_name: true\nlabels:\n  dc: 'a'\n",
+                "host1:9100",
+                Some((
+                    "https://host1:9100/stats",
+                    vec![
+                        ("instance", "host1:9100"),
+                        ("job", "node"),
+                        ("env", "prod"),
+                        ("dc", "a"),
+                    ],
+                )),
+            ),
+            // blackbox exporter style, the target becomes a parameter
+            (
+                "name: blackbox\nmetrics_path: /probe\nsuppress_scrape_name: true\nparams:\n  module: ['http_2xx']\nrelabel_configs:\n  - source_labels: ['__address__']\n    target_label: '__param_target'\n  - source_labels: ['__param_target']\n    target_label: 'instance'\n  - target_label: '__address__'\n    replacement: 'blackbox:9115'\n",
+                "https://www.example.com",
+                Some((
+                    "http://blackbox:9115/probe?module=http_2xx&target=https%3A%2F%2Fwww.example.com",
+                    vec![
+                        ("instance", "https://www.example.com"),
+                        ("job", "blackbox"),
+                        ("env", "prod"),
+                    ],
+                )),
+            ),
+            // __param_<name> replaces the first value of the configured parameter
+            (
+                "name: node\nsuppress_scrape_name: true\nparams:\n  collect[]: ['cpu', 'meminfo']\nrelabel_configs:\n  - target_label: '__param_collect[]'\n    replacement: 'disk'\n",
+                "host1:9100",
+                Some((
+                    "http://host1:9100/metrics?collect%5B%5D=disk&collect%5B%5D=meminfo",
+                    vec![("instance", "host1:9100"), ("job", "node"), ("env", "prod")],
+                )),
+            ),
+            (
+                "name: node\nrelabel_configs:\n  - source_labels: ['env']\n    regex: 'prod'\n    action: drop\n",
+                "host1:9100",
+                None,
+            ),
+            (
+                "name: node\nrelabel_configs:\n  - target_label: '__address__'\n    replacement: ''\n",
+                "host1:9100",
+                None,
+            ),
+            (
+                "name: node\nrelabel_configs:\n  - target_label: '__scheme__'\n    replacement: 'not a scheme'\n",
+                "host1:9100",
+                None,
+            ),
+        ];
+
+        for (yaml, target, expected) in cases {
+            let j = job(yaml);
+            let result = build_target(&j, &relabeler(&j), target, &group, "targets.yaml");
+            match expected {
+                None => assert!(result.is_none(), "job:\n{}", yaml),
+                Some((url, labels)) => {
+                    let scrape = result.unwrap();
+                    assert_eq!(scrape.url, url, "job:\n{}", yaml);
+                    assert_eq!(
+                        scrape.labels,
+                        labels
+                            .iter()
+                            .map(|(k, v)| (k.to_string(), v.to_string()))
+                            .collect::<HashMap<String, String>>(),
+                        "job:\n{}",
+                        yaml
+                    );
+                    assert_eq!(scrape.name, format!("{}/{}", j.name, target));
+                    assert_eq!(scrape.sd_file, "targets.yaml");
+                    assert!(scrape.static_configs.is_empty());
+                }
+            };
+        }
+    }
+}
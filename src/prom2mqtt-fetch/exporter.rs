@@ -2,8 +2,10 @@ use crate::constants;
 
 use lazy_static::lazy_static;
 use log::error;
+use prometheus::core::Collector;
 use prometheus::{
-    Histogram, HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+    Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
 };
 
 lazy_static! {
@@ -25,6 +27,22 @@ lazy_static! {
         &["scrape_name"],
     )
     .unwrap();
+    pub static ref SCRAPE_RETRIES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            constants::METRIC_SCRAPE_RETRIES_NAME,
+            constants::METRIC_SCRAPE_RETRIES_HELP
+        ),
+        &["scrape_name"],
+    )
+    .unwrap();
+    pub static ref SCRAPE_HTTP_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            constants::METRIC_SCRAPE_HTTP_ERRORS_NAME,
+            constants::METRIC_SCRAPE_HTTP_ERRORS_HELP
+        ),
+        &["scrape_name", "code"],
+    )
+    .unwrap();
     pub static ref COMPRESSION: IntGauge = IntGauge::new(
         constants::METRIC_COMPRESSION_NAME,
         constants::METRIC_COMPRESSION_HELP
@@ -73,6 +91,10 @@ pub fn register() {
         .register(Box::new(SCRAPE_DURATION.clone()))
         .unwrap();
     REGISTRY.register(Box::new(SCRAPE_SUCCESS.clone())).unwrap();
+    REGISTRY.register(Box::new(SCRAPE_RETRIES.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(SCRAPE_HTTP_ERRORS.clone()))
+        .unwrap();
     REGISTRY.register(Box::new(COMPRESSION.clone())).unwrap();
     REGISTRY.register(Box::new(SIZE.clone())).unwrap();
     REGISTRY
@@ -84,6 +106,24 @@ pub fn register() {
     REGISTRY.register(Box::new(MQTT_SUCCESS.clone())).unwrap();
 }
 
+// Remove the HTTP error counters of a scrape job for all status codes
+pub fn remove_http_errors(scrape_name: &str) {
+    for family in SCRAPE_HTTP_ERRORS.collect() {
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            if !labels
+                .iter()
+                .any(|l| l.get_name() == "scrape_name" && l.get_value() == scrape_name)
+            {
+                continue;
+            }
+            if let Some(code) = labels.iter().find(|l| l.get_name() == "code") {
+                let _ = SCRAPE_HTTP_ERRORS.remove_label_values(&[scrape_name, code.get_value()]);
+            }
+        }
+    }
+}
+
 pub fn metrics() -> String {
     let encoder = TextEncoder::new();
     let mut buffer = String::new();
@@ -1,84 +1,452 @@
 use crate::config;
 use crate::constants;
 use crate::exporter;
+use crate::massage;
+use crate::reload;
+use crate::unix;
 
+use base64::Engine;
+use flate2::read::{GzDecoder, ZlibDecoder};
 use log::{debug, error, info};
 use simple_error::bail;
 use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
 use std::time;
+use url::Url;
 
-pub fn build_http_client(timeout: u64) -> Result<reqwest::blocking::Client, Box<dyn Error>> {
-    let dtimeout = time::Duration::from_secs(timeout);
+// Scrape targets are fetched by reqwest or, if they listen on a Unix domain socket, by unix::Client
+#[derive(Clone, Debug)]
+pub enum Client {
+    Http(reqwest::blocking::Client),
+    Unix(unix::Client),
+}
+
+// Reply of a scrape target, the body is still encoded as described by content_encoding
+pub struct Reply {
+    pub body: Vec<u8>,
+    pub content_encoding: String,
+    pub content_type: String,
+    pub status: u16,
+    // status code and reason phrase as shown in log messages
+    pub status_line: String,
+}
+
+// Reply of a scrape target with an unexpected HTTP status code
+#[derive(Debug)]
+pub struct StatusError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for StatusError {}
+
+// Why a scrape failed, used to decide whether it is retried
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    Error(config::RetryError),
+    Status(u16),
+    Other,
+}
+
+// The chain of error sources is searched for HTTP status codes and connection errors
+pub fn classify(e: &(dyn Error + 'static)) -> Failure {
+    let mut current = Some(e);
+    while let Some(err) = current {
+        if let Some(v) = err.downcast_ref::<StatusError>() {
+            return Failure::Status(v.code);
+        }
+        if let Some(v) = err.downcast_ref::<reqwest::Error>() {
+            if v.is_timeout() {
+                return Failure::Error(config::RetryError::Timeout);
+            }
+            if v.is_connect() {
+                return Failure::Error(config::RetryError::Connect);
+            }
+        }
+        if let Some(v) = err.downcast_ref::<io::Error>() {
+            match v.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                    return Failure::Error(config::RetryError::Timeout)
+                }
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::AddrNotAvailable
+                | io::ErrorKind::NotFound => return Failure::Error(config::RetryError::Connect),
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof => return Failure::Error(config::RetryError::Reset),
+                _ => {}
+            };
+        }
+        current = err.source();
+    }
+    Failure::Other
+}
+
+pub fn build_client(
+    scrape: &config::Scrape,
+    default_timeout: u64,
+) -> Result<Client, Box<dyn Error>> {
+    let dtimeout = time::Duration::from_secs(scrape.timeout.unwrap_or(default_timeout));
     let mut headers = reqwest::header::HeaderMap::new();
 
     headers.insert(
         "X-Clacks-Overhead",
         reqwest::header::HeaderValue::from_static("GNU Terry Pratchett"),
     );
+    // can be overridden by the headers of the scrape job
+    headers.insert(
+        reqwest::header::ACCEPT,
+        reqwest::header::HeaderValue::from_static(constants::SCRAPE_ACCEPT),
+    );
+    headers.insert(
+        reqwest::header::ACCEPT_ENCODING,
+        reqwest::header::HeaderValue::from_static(constants::SCRAPE_ACCEPT_ENCODING),
+    );
+
+    for (name, value) in scrape.headers.iter() {
+        let mut value = reqwest::header::HeaderValue::from_str(value.expose())?;
+        value.set_sensitive(true);
+        headers.insert(
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())?,
+            value,
+        );
+    }
+
+    if let Some(v) = &scrape.basic_auth {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(format!(
+            "{}:{}",
+            v.username,
+            v.password.expose()
+        ));
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Basic {}", encoded))?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+
+    if let Some(v) = &scrape.authorization {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!(
+            "{} {}",
+            v.auth_type,
+            v.credentials.expose()
+        ))?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+
+    if !scrape.socket_path.is_empty() {
+        headers.insert(
+            reqwest::header::USER_AGENT,
+            reqwest::header::HeaderValue::from_str(&constants::generate_user_agent())?,
+        );
+        return Ok(Client::Unix(unix::Client::new(
+            &scrape.socket_path,
+            dtimeout,
+            headers,
+        )));
+    }
 
-    let http_client_builder = reqwest::blocking::ClientBuilder::new()
+    let mut http_client_builder = reqwest::blocking::ClientBuilder::new()
         .user_agent(constants::generate_user_agent())
-        .default_headers(headers)
         .timeout(dtimeout);
-    let http_client = match http_client_builder.build() {
+
+    if let Some(tls) = &scrape.tls_config {
+        if !tls.ca_file.is_empty() {
+            let pem = match fs::read(&tls.ca_file) {
+                Ok(v) => v,
+                Err(e) => bail!("can't read CA file {} - {}", tls.ca_file, e),
+            };
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+                http_client_builder = http_client_builder.add_root_certificate(cert);
+            }
+        }
+
+        if !tls.cert_file.is_empty() {
+            let cert = match fs::read(&tls.cert_file) {
+                Ok(v) => v,
+                Err(e) => bail!("can't read certificate file {} - {}", tls.cert_file, e),
+            };
+            let key = match fs::read(&tls.key_file) {
+                Ok(v) => v,
+                Err(e) => bail!("can't read key file {} - {}", tls.key_file, e),
+            };
+            // the TLS backend only accepts PKCS#8 keys
+            let key =
+                openssl::pkey::PKey::private_key_from_pem(&key)?.private_key_to_pem_pkcs8()?;
+            http_client_builder =
+                http_client_builder.identity(reqwest::Identity::from_pkcs8_pem(&cert, &key)?);
+        }
+
+        if tls.insecure_skip_verify {
+            http_client_builder = http_client_builder.danger_accept_invalid_certs(true);
+        }
+
+        // The certificate is verified against server_name by requesting server_name (see request_url) and
+        // resolving it to the address of the original host. reqwest can't set the name per connection, so
+        // the address is pinned here and the client is recreated periodically (see scrape::prepare)
+        if !tls.server_name.is_empty() {
+            let url = Url::parse(&scrape.url)?;
+            let addr = match url.socket_addrs(|| None)?.first() {
+                Some(v) => *v,
+                None => bail!("can't resolve address of {}", scrape.url),
+            };
+            http_client_builder = http_client_builder.resolve(&tls.server_name, addr);
+
+            if let Some(host) = url.host_str() {
+                let authority = match url.port() {
+                    Some(p) => format!("{}:{}", host, p),
+                    None => host.to_string(),
+                };
+                headers.insert(
+                    reqwest::header::HOST,
+                    reqwest::header::HeaderValue::from_str(&authority)?,
+                );
+            }
+        }
+    }
+
+    if !scrape.proxy_url.is_empty() {
+        http_client_builder =
+            http_client_builder.proxy(reqwest::Proxy::all(scrape.proxy_url.expose())?);
+    }
+
+    let http_client = match http_client_builder.default_headers(headers).build() {
         Ok(v) => v,
         Err(e) => bail!("can't create HTTP client: {}", e),
     };
-    Ok(http_client)
+    Ok(Client::Http(http_client))
+}
+
+// URL to request, the host is replaced by the server_name of the TLS configuration if set
+pub fn request_url(scrape: &config::Scrape) -> Result<String, Box<dyn Error>> {
+    if let Some(tls) = &scrape.tls_config {
+        if !tls.server_name.is_empty() {
+            let mut url = Url::parse(&scrape.url)?;
+            url.set_host(Some(&tls.server_name))?;
+            return Ok(url.to_string());
+        }
+    }
+    Ok(scrape.url.clone())
+}
+
+// URL shown in log messages, including the socket for targets listening on a Unix socket
+pub fn display_url(scrape: &config::Scrape) -> String {
+    if scrape.socket_path.is_empty() {
+        return scrape.url.clone();
+    }
+    format!("{} (socket {})", scrape.url, scrape.socket_path)
+}
+
+// Fetch the data of a scrape target, every 2xx status code is accepted. The body is decompressed and
+// converted into the Prometheus text format according to the content encoding and type of the reply
+pub fn get(client: &Client, url: &str) -> Result<String, Box<dyn Error>> {
+    let reply = match client {
+        Client::Http(v) => {
+            debug!("sending HTTP GET request to {}", url);
+            let r = v.get(url).send()?;
+            let header = |name: reqwest::header::HeaderName| {
+                r.headers()
+                    .get(name)
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            Reply {
+                content_encoding: header(reqwest::header::CONTENT_ENCODING),
+                content_type: header(reqwest::header::CONTENT_TYPE),
+                status: r.status().as_u16(),
+                status_line: r.status().to_string(),
+                body: r.bytes()?.to_vec(),
+            }
+        }
+        Client::Unix(v) => v.get(url)?,
+    };
+
+    if !(200..300).contains(&reply.status) {
+        return Err(Box::new(StatusError {
+            code: reply.status,
+            message: format!("HTTP request to {} returned {}", url, reply.status_line),
+        }));
+    }
+    decode(reply, url)
 }
 
-pub fn get(client: &reqwest::blocking::Client, url: &str) -> Result<String, Box<dyn Error>> {
-    debug!("sending HTTP GET request to {}", url);
-    let reply = client.get(url).send()?;
-    if reply.status() != reqwest::StatusCode::OK {
+fn decode(reply: Reply, url: &str) -> Result<String, Box<dyn Error>> {
+    let encoded_size = reply.body.len();
+    let encoding = reply.content_encoding.trim().to_lowercase();
+    let body = match encoding.as_str() {
+        "" | "identity" => reply.body,
+        "gzip" | "x-gzip" => decompress(
+            GzDecoder::new(&reply.body[..]),
+            url,
+            constants::SCRAPE_MAX_DECOMPRESSED_SIZE,
+        )?,
+        "deflate" => decompress(
+            ZlibDecoder::new(&reply.body[..]),
+            url,
+            constants::SCRAPE_MAX_DECOMPRESSED_SIZE,
+        )?,
+        _ => bail!(
+            "unsupported content encoding {} in reply from {}",
+            reply.content_encoding,
+            url
+        ),
+    };
+    if !encoding.is_empty() && encoding != "identity" {
+        debug!(
+            "decompressed {} bytes of {} encoded data from {} to {} bytes",
+            encoded_size,
+            encoding,
+            url,
+            body.len()
+        );
+    }
+    let body = String::from_utf8(body)?;
+
+    // parameters like version and charset are ignored
+    let media_type = reply
+        .content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    match media_type.as_str() {
+        constants::CONTENT_TYPE_OPENMETRICS => Ok(massage::openmetrics_to_text(&body)),
+        "" | constants::CONTENT_TYPE_TEXT => Ok(body),
+        _ => {
+            debug!(
+                "unexpected content type {} in reply from {}, parsing it as Prometheus text format",
+                reply.content_type, url
+            );
+            Ok(body)
+        }
+    }
+}
+
+// Fails if the decompressed data is larger than limit bytes
+fn decompress<R: Read>(decoder: R, url: &str, limit: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut result: Vec<u8> = Vec::new();
+    if let Err(e) = decoder.take(limit + 1).read_to_end(&mut result) {
+        bail!("can't decompress reply from {} - {}", url, e);
+    }
+    if result.len() as u64 > limit {
         bail!(
-            "HTTP request to {} returned {} instead of \"200 OK\"",
+            "decompressed reply from {} exceeds the limit of {} bytes",
             url,
-            reply.status()
+            limit
         );
     }
-    Ok(reply.text()?)
+    Ok(result)
 }
 
-pub fn run(cfg: &config::Configuration) -> Result<(), Box<dyn Error>> {
+pub fn run(
+    cfg: &config::Configuration,
+    health: Arc<global::health::Health>,
+    reload_request: mpsc::Sender<reload::Request>,
+    reload: mpsc::Receiver<config::Configuration>,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn Error>> {
     let headers: Vec<tiny_http::Header> = vec![
         tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
         tiny_http::Header::from_bytes(&b"X-Clacks-Overhead"[..], &b"GNU Terry Pratchett"[..])
             .unwrap(),
     ];
 
-    let server = match tiny_http::Server::http(&cfg.prometheus.listen) {
-        Ok(v) => v,
-        Err(e) => bail!("cant start HTTP server - {}", e),
-    };
+    let server = global::web::server(&cfg.prometheus.listen, &cfg.web)?;
+    let mut auth = global::web::Authenticator::new(&cfg.web);
     info!(
-        "listening on http://{}{} for prometheus metric scrapes",
-        cfg.prometheus.listen, cfg.prometheus.path
+        "listening on {}://{}{} for prometheus metric scrapes",
+        global::web::scheme(&cfg.web),
+        cfg.prometheus.listen,
+        cfg.prometheus.path
     );
 
-    let mpath = &cfg.prometheus.path;
+    let mut mpath = cfg.prometheus.path.clone();
+    let one_second = time::Duration::from_secs(1);
 
     loop {
-        let request = match server.recv() {
-            Ok(v) => v,
+        if global::shutdown::requested(&stop) {
+            info!("stopping HTTP server");
+            return Ok(());
+        }
+
+        // don't block forever, a shutdown request must be noticed
+        let request = match server.recv_timeout(one_second) {
+            Ok(Some(v)) => v,
+            Ok(None) => continue,
             Err(e) => {
                 error!("can't process incoming HTTP  request: {}", e);
                 continue;
             }
         };
+        if let Ok(v) = reload.try_recv() {
+            auth = global::web::Authenticator::new(&v.web);
+            mpath = v.prometheus.path;
+        }
+
         let method = request.method();
         let url = request.url();
         let status_code: tiny_http::StatusCode;
         let payload: String;
-        let http_header = headers.clone();
+        let mut http_header = headers.clone();
 
-        if method == &tiny_http::Method::Get {
+        if !auth.authorized(&request) {
+            status_code = tiny_http::StatusCode::from(401_i16);
+            payload = constants::HTTP_UNAUTHORIZED.to_string();
+            http_header.push(global::web::unauthorized_header());
+        } else if url == global::constants::HTTP_RELOAD_PATH {
+            if method == &tiny_http::Method::Post || method == &tiny_http::Method::Put {
+                debug!("sending reload request");
+                let (reply_send, reply_recv) = mpsc::channel::<Result<(), String>>();
+                reload_request.send(reload::Request {
+                    reply: Some(reply_send),
+                })?;
+                match reply_recv.recv()? {
+                    Ok(_) => {
+                        status_code = tiny_http::StatusCode::from(200_i16);
+                        payload = global::constants::HTTP_RELOADED.to_string();
+                    }
+                    Err(e) => {
+                        status_code = tiny_http::StatusCode::from(500_i16);
+                        payload = format!("{}\n", e);
+                    }
+                };
+            } else {
+                status_code = tiny_http::StatusCode::from(405_i16);
+                payload = constants::HTTP_METHOD_NOT_ALLOWED.to_string();
+            }
+        } else if method == &tiny_http::Method::Get {
             if url == "/" {
                 status_code = tiny_http::StatusCode::from(302_i16);
                 payload = constants::HTML_ROOT.to_string();
             } else if url == mpath {
                 status_code = tiny_http::StatusCode::from(200_i16);
                 payload = exporter::metrics();
+            } else if url == global::constants::HTTP_HEALTHY_PATH {
+                status_code = tiny_http::StatusCode::from(200_i16);
+                payload = global::constants::HTTP_HEALTHY.to_string();
+            } else if url == global::constants::HTTP_READY_PATH {
+                if !health.is_connected() {
+                    status_code = tiny_http::StatusCode::from(503_i16);
+                    payload = global::constants::HTTP_NOT_CONNECTED.to_string();
+                } else if !health.is_operational() {
+                    status_code = tiny_http::StatusCode::from(503_i16);
+                    payload = constants::HTTP_NOT_PUBLISHED.to_string();
+                } else {
+                    status_code = tiny_http::StatusCode::from(200_i16);
+                    payload = global::constants::HTTP_READY.to_string();
+                }
             } else {
                 status_code = tiny_http::StatusCode::from(404_i16);
                 payload = constants::HTTP_NOT_FOUND.to_string();
@@ -87,6 +455,7 @@ pub fn run(cfg: &config::Configuration) -> Result<(), Box<dyn Error>> {
             status_code = tiny_http::StatusCode::from(405_i16);
             payload = constants::HTTP_METHOD_NOT_ALLOWED.to_string();
         }
+
         if let Err(e) = request.respond(tiny_http::Response::new(
             status_code,
             http_header,
@@ -98,3 +467,212 @@ pub fn run(cfg: &config::Configuration) -> Result<(), Box<dyn Error>> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Error wrapping another error, like the errors of reqwest and hyper
+    #[derive(Debug)]
+    struct Wrapped(Box<dyn Error + 'static>);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "wrapped: {}", self.0)
+        }
+    }
+
+    impl Error for Wrapped {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(self.0.as_ref())
+        }
+    }
+
+    fn io_failure(kind: io::ErrorKind) -> Failure {
+        classify(&io::Error::new(kind, "test"))
+    }
+
+    #[test]
+    fn classify_status_codes() {
+        let e = StatusError {
+            code: 503,
+            message: "HTTP request returned 503 Service Unavailable".to_string(),
+        };
+        assert_eq!(classify(&e), Failure::Status(503));
+        assert_eq!(classify(&Wrapped(Box::new(e))), Failure::Status(503));
+    }
+
+    #[test]
+    fn classify_io_errors() {
+        for kind in [io::ErrorKind::TimedOut, io::ErrorKind::WouldBlock] {
+            assert_eq!(
+                io_failure(kind),
+                Failure::Error(config::RetryError::Timeout)
+            );
+        }
+        for kind in [
+            io::ErrorKind::ConnectionRefused,
+            io::ErrorKind::AddrNotAvailable,
+            io::ErrorKind::NotFound,
+        ] {
+            assert_eq!(
+                io_failure(kind),
+                Failure::Error(config::RetryError::Connect)
+            );
+        }
+        for kind in [
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::ConnectionAborted,
+            io::ErrorKind::BrokenPipe,
+            io::ErrorKind::UnexpectedEof,
+        ] {
+            assert_eq!(io_failure(kind), Failure::Error(config::RetryError::Reset));
+        }
+        for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::InvalidData] {
+            assert_eq!(io_failure(kind), Failure::Other);
+        }
+
+        let e = Wrapped(Box::new(io::Error::from(io::ErrorKind::ConnectionReset)));
+        assert_eq!(classify(&e), Failure::Error(config::RetryError::Reset));
+    }
+
+    #[test]
+    fn classify_reqwest_errors() {
+        // nothing listens on port 1
+        let client = reqwest::blocking::Client::new();
+        let e = client.get("http://127.0.0.1:1/metrics").send().unwrap_err();
+        assert_eq!(classify(&e), Failure::Error(config::RetryError::Connect));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        let client = reqwest::blocking::Client::builder()
+            .timeout(time::Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let e = client.get(url).send().unwrap_err();
+        assert_eq!(classify(&e), Failure::Error(config::RetryError::Timeout));
+        drop(listener);
+    }
+
+    #[test]
+    fn client_headers_are_sensitive() {
+        for url in [
+            "https://127.0.0.1:9100/metrics",
+            "http://localhost/metrics\nsocket_path: /run/exporter.sock",
+        ] {
+            let scrape: config::Scrape = serde_yaml::from_str(&format!(
+                "name: api\nurl: {}\nheaders:\n  X-Api-Key: key-1234\n",
+                url
+            ))
+            .unwrap();
+            let client = build_client(&scrape, 10).unwrap();
+            assert!(!format!("{:?}", client).contains("key-1234"));
+        }
+    }
+
+    #[test]
+    fn classify_other_errors() {
+        let e = simple_error::SimpleError::new("can't parse data");
+        assert_eq!(classify(&e), Failure::Other);
+        assert_eq!(classify(&Wrapped(Box::new(e))), Failure::Other);
+    }
+
+    const OPENMETRICS: &str = "# HELP http_requests Requests.\n# TYPE http_requests counter\nhttp_requests_total{code=\"200\"} 17 1520879607.789\nhttp_requests_created{code=\"200\"} 1520430000.123\n# EOF\n";
+    const TEXT: &str = "# HELP http_requests_total Requests.\n# TYPE http_requests_total counter\nhttp_requests_total{code=\"200\"} 17 1520879607789\n";
+
+    fn reply(body: Vec<u8>, content_encoding: &str, content_type: &str) -> Reply {
+        Reply {
+            body,
+            content_encoding: content_encoding.to_string(),
+            content_type: content_type.to_string(),
+            status: 200,
+            status_line: "200 OK".to_string(),
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decode_content_encodings() {
+        let url = "http://127.0.0.1:9100/metrics";
+        let text_type = "text/plain; version=0.0.4; charset=utf-8";
+        for (body, encoding) in [
+            (TEXT.as_bytes().to_vec(), ""),
+            (TEXT.as_bytes().to_vec(), "identity"),
+            (gzip(TEXT.as_bytes()), "gzip"),
+            (gzip(TEXT.as_bytes()), "x-gzip"),
+            (gzip(TEXT.as_bytes()), " GZIP "),
+            (deflate(TEXT.as_bytes()), "deflate"),
+        ] {
+            assert_eq!(
+                decode(reply(body, encoding, text_type), url).unwrap(),
+                TEXT,
+                "{}",
+                encoding
+            );
+        }
+
+        assert!(decode(reply(gzip(TEXT.as_bytes()), "br", text_type), url).is_err());
+        // corrupted and mislabeled data
+        let mut corrupted = gzip(TEXT.as_bytes());
+        corrupted.truncate(corrupted.len() / 2);
+        assert!(decode(reply(corrupted, "gzip", text_type), url).is_err());
+        assert!(decode(reply(deflate(TEXT.as_bytes()), "gzip", text_type), url).is_err());
+        assert!(decode(reply(TEXT.as_bytes().to_vec(), "deflate", text_type), url).is_err());
+    }
+
+    #[test]
+    fn decompressed_size_is_limited() {
+        let url = "http://127.0.0.1:9100/metrics";
+        let data = vec![b'0'; 4096];
+        assert_eq!(
+            decompress(GzDecoder::new(&gzip(&data)[..]), url, 4096).unwrap(),
+            data
+        );
+        assert_eq!(
+            decompress(ZlibDecoder::new(&deflate(&data)[..]), url, 4096).unwrap(),
+            data
+        );
+
+        let e = decompress(GzDecoder::new(&gzip(&data)[..]), url, 4095).unwrap_err();
+        assert!(e.to_string().contains("exceeds the limit of 4095 bytes"));
+        let e = decompress(ZlibDecoder::new(&deflate(&data)[..]), url, 1024).unwrap_err();
+        assert!(e.to_string().contains("exceeds the limit of 1024 bytes"));
+    }
+
+    #[test]
+    fn decode_content_types() {
+        let url = "http://127.0.0.1:9100/metrics";
+        for content_type in [
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            "Application/OpenMetrics-Text",
+        ] {
+            assert_eq!(
+                decode(
+                    reply(gzip(OPENMETRICS.as_bytes()), "gzip", content_type),
+                    url
+                )
+                .unwrap(),
+                TEXT
+            );
+        }
+        // unknown content types are parsed as Prometheus text format
+        for content_type in ["", "text/plain", "application/octet-stream"] {
+            assert_eq!(
+                decode(reply(TEXT.as_bytes().to_vec(), "", content_type), url).unwrap(),
+                TEXT
+            );
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use crate::config;
+use crate::constants;
+use crate::relabel;
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use simple_error::bail;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// The part of a Prometheus configuration file used to import scrape jobs
+#[derive(Debug, Deserialize)]
+struct PrometheusConfig {
+    #[serde(default)]
+    global: PrometheusGlobal,
+    #[serde(default)]
+    scrape_config_files: Vec<String>,
+    #[serde(default)]
+    scrape_configs: Vec<serde_yaml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PrometheusGlobal {
+    scrape_interval: Option<String>,
+    scrape_timeout: Option<String>,
+}
+
+// Supported subset of a Prometheus scrape_config, all other settings are collected in unsupported
+#[derive(Debug, Deserialize)]
+struct ScrapeConfig {
+    authorization: Option<config::Authorization>,
+    basic_auth: Option<config::BasicAuth>,
+    bearer_token: Option<String>,
+    bearer_token_file: Option<String>,
+    #[serde(default)]
+    file_sd_configs: Vec<FileSdConfig>,
+    job_name: String,
+    #[serde(default)]
+    metric_relabel_configs: Vec<relabel::RelabelConfig>,
+    metrics_path: Option<String>,
+    #[serde(default)]
+    params: HashMap<String, Vec<String>>,
+    proxy_url: Option<String>,
+    #[serde(default)]
+    relabel_configs: Vec<relabel::RelabelConfig>,
+    scheme: Option<String>,
+    scrape_interval: Option<String>,
+    scrape_timeout: Option<String>,
+    #[serde(default)]
+    static_configs: Vec<config::TargetGroup>,
+    tls_config: Option<TlsConfig>,
+    #[serde(flatten)]
+    unsupported: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileSdConfig {
+    files: Vec<String>,
+    refresh_interval: Option<String>,
+    #[serde(flatten)]
+    unsupported: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TlsConfig {
+    #[serde(flatten)]
+    supported: config::TlsConfig,
+    #[serde(flatten)]
+    unsupported: BTreeMap<String, serde_yaml::Value>,
+}
+
+// Settings of config::TlsConfig, they are passed on instead of being reported as unsupported
+const TLS_CONFIG_SETTINGS: [&str; 5] = [
+    "ca_file",
+    "cert_file",
+    "insecure_skip_verify",
+    "key_file",
+    "server_name",
+];
+
+// Read the scrape_configs of a Prometheus configuration file and convert them into scrape jobs.
+// Jobs using unsupported service discovery mechanisms or relabel actions are skipped with a warning,
+// other unsupported settings are ignored with a warning
+pub fn scrape_jobs(cfg: &config::Import) -> Result<Vec<config::Scrape>, Box<dyn Error>> {
+    let raw = match fs::read_to_string(&cfg.prometheus_config) {
+        Ok(v) => v,
+        Err(e) => bail!("can't read {} - {}", cfg.prometheus_config, e),
+    };
+    let parsed: PrometheusConfig = match serde_yaml::from_str(&raw) {
+        Ok(v) => v,
+        Err(e) => bail!("can't parse {} - {}", cfg.prometheus_config, e),
+    };
+    // relative file names are relative to the directory of the Prometheus configuration file
+    let base = Path::new(&cfg.prometheus_config)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+
+    if !parsed.scrape_config_files.is_empty() {
+        warn!(
+            "{}: scrape_config_files are not supported, ignoring them",
+            cfg.prometheus_config
+        );
+    }
+
+    let interval = match &parsed.global.scrape_interval {
+        Some(v) => Some(parse_duration(v)?),
+        None => None,
+    };
+    let timeout = match &parsed.global.scrape_timeout {
+        Some(v) => Some(parse_duration(v)?),
+        None => None,
+    };
+
+    let mut jobs: Vec<config::Scrape> = Vec::new();
+    for value in parsed.scrape_configs.into_iter() {
+        let job_name = value
+            .get("job_name")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        if !cfg.jobs.is_empty() && !cfg.jobs.contains(&job_name) {
+            debug!("'{}': job is not selected for import", job_name);
+            continue;
+        }
+
+        let scrape_config: ScrapeConfig = match serde_yaml::from_value(value) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(
+                    "'{}': can't import scrape config, skipping it - {}",
+                    job_name, e
+                );
+                continue;
+            }
+        };
+        match convert(scrape_config, &base, interval, timeout) {
+            Ok(Some(v)) => {
+                info!(
+                    "'{}': imported scrape job from {}",
+                    v.name, cfg.prometheus_config
+                );
+                jobs.push(v);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "'{}': can't import scrape config, skipping it - {}",
+                job_name, e
+            ),
+        };
+    }
+
+    for name in cfg.jobs.iter() {
+        if !jobs.iter().any(|j| &j.name == name) {
+            warn!(
+                "'{}': job not found in {} or not imported",
+                name, cfg.prometheus_config
+            );
+        }
+    }
+
+    Ok(jobs)
+}
+
+// Returns None if the job can't be scraped without the unsupported settings
+fn convert(
+    sc: ScrapeConfig,
+    base: &Path,
+    global_interval: Option<u64>,
+    global_timeout: Option<u64>,
+) -> Result<Option<config::Scrape>, Box<dyn Error>> {
+    let name = sc.job_name;
+    if let Err(e) = config::validate_name(&name) {
+        warn!("'{}': unsupported job name, skipping job - {}", name, e);
+        return Ok(None);
+    }
+
+    for (key, _) in sc.unsupported.iter() {
+        if key.ends_with("_sd_configs") {
+            warn!(
+                "'{}': {} are not supported, only static_configs and file_sd_configs are imported",
+                name, key
+            );
+        } else {
+            warn!("'{}': setting {} is not supported, ignoring it", name, key);
+        }
+    }
+
+    if sc.static_configs.is_empty() && sc.file_sd_configs.is_empty() {
+        warn!(
+            "'{}': no static_configs or file_sd_configs found, skipping job",
+            name
+        );
+        return Ok(None);
+    }
+
+    let mut file_sd_configs: Vec<config::FileSdConfig> = Vec::new();
+    for sd in sc.file_sd_configs.iter() {
+        for (key, _) in sd.unsupported.iter() {
+            warn!(
+                "'{}': setting {} of file_sd_configs is not supported, ignoring it",
+                name, key
+            );
+        }
+        let refresh_interval = match &sd.refresh_interval {
+            Some(v) => parse_duration(v)?,
+            None => constants::DEFAULT_FILE_SD_REFRESH_INTERVAL,
+        };
+        file_sd_configs.push(config::FileSdConfig {
+            files: sd.files.iter().map(|f| resolve(base, f)).collect(),
+            refresh_interval,
+        });
+    }
+
+    let mut authorization = sc.authorization;
+    if let Some(v) = authorization.as_mut() {
+        v.credentials_file = resolve(base, &v.credentials_file);
+    }
+    // bearer_token and bearer_token_file are deprecated in Prometheus in favour of authorization
+    if sc.bearer_token.is_some() || sc.bearer_token_file.is_some() {
+        if authorization.is_some() {
+            bail!("only one of authorization and bearer_token can be set");
+        }
+        authorization = Some(config::Authorization {
+            credentials: sc.bearer_token.unwrap_or_default().into(),
+            credentials_file: resolve(base, &sc.bearer_token_file.unwrap_or_default()),
+            auth_type: constants::DEFAULT_AUTHORIZATION_TYPE.to_string(),
+        });
+    }
+
+    let mut basic_auth = sc.basic_auth;
+    if let Some(v) = basic_auth.as_mut() {
+        v.password_file = resolve(base, &v.password_file);
+    }
+
+    let tls_config = match sc.tls_config {
+        Some(v) => {
+            for (key, _) in v.unsupported.iter() {
+                if !TLS_CONFIG_SETTINGS.contains(&key.as_str()) {
+                    warn!(
+                        "'{}': setting {} of tls_config is not supported, ignoring it",
+                        name, key
+                    );
+                }
+            }
+            let mut tls = v.supported;
+            tls.ca_file = resolve(base, &tls.ca_file);
+            tls.cert_file = resolve(base, &tls.cert_file);
+            tls.key_file = resolve(base, &tls.key_file);
+            Some(tls)
+        }
+        None => None,
+    };
+
+    let interval = match &sc.scrape_interval {
+        Some(v) => Some(parse_duration(v)?),
+        None => global_interval,
+    };
+    let timeout = match &sc.scrape_timeout {
+        Some(v) => Some(parse_duration(v)?),
+        None => global_timeout,
+    };
+
+    Ok(Some(config::Scrape {
+        authorization,
+        basic_auth,
+        exec: None,
+        file_sd_configs,
+        headers: HashMap::new(),
+        http_client: None,
+        http_client_resolved: None,
+        interval: interval.map(|v| v as i64),
+        labels: HashMap::new(),
+        last_data: None,
+        metric_relabel_configs: sc.metric_relabel_configs,
+        metric_relabeler: None,
+        metrics_path: sc
+            .metrics_path
+            .unwrap_or(constants::DEFAULT_SCRAPE_METRICS_PATH.to_string()),
+        name,
+        next_scrape: None,
+        on_failure: config::OnFailure::default(),
+        params: sc.params,
+        proxy_url: sc.proxy_url.unwrap_or_default().into(),
+        relabel_configs: sc.relabel_configs,
+        scheme: sc
+            .scheme
+            .unwrap_or(constants::DEFAULT_SCRAPE_SCHEME.to_string()),
+        resends: 0,
+        retry: config::Retry::default(),
+        sd_file: String::new(),
+        socket_path: String::new(),
+        static_configs: sc.static_configs,
+        suppress_scrape_name: false,
+        textfile: None,
+        timeout,
+        tls_config,
+        url: String::new(),
+    }))
+}
+
+fn resolve(base: &Path, file: &str) -> String {
+    if file.is_empty() || Path::new(file).is_absolute() {
+        return file.to_string();
+    }
+    base.join(file).to_string_lossy().to_string()
+}
+
+// Parse a Prometheus duration like 1m30s into seconds, fractions of a second are not supported.
+// Like Prometheus, each unit can only be used once and units must be ordered from largest to smallest
+fn parse_duration(s: &str) -> Result<u64, Box<dyn Error>> {
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    let mut chars = s.chars().peekable();
+    let mut previous: Option<u64> = None;
+
+    if s.is_empty() {
+        bail!("empty duration");
+    }
+
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        if number.is_empty() {
+            bail!("invalid duration '{}'", s);
+        }
+        let value: u64 = match number.parse() {
+            Ok(v) => v,
+            Err(e) => bail!("invalid duration '{}' - {}", s, e),
+        };
+        number.clear();
+
+        let factor: u64 = match c {
+            'y' => 365 * 24 * 3600,
+            'w' => 7 * 24 * 3600,
+            'd' => 24 * 3600,
+            'h' => 3600,
+            'm' if chars.peek() == Some(&'s') => {
+                bail!(
+                    "invalid duration '{}', durations below one second are not supported",
+                    s
+                )
+            }
+            'm' => 60,
+            's' => 1,
+            _ => bail!("invalid duration '{}', unknown unit {}", s, c),
+        };
+        if previous.is_some_and(|p| p <= factor) {
+            bail!(
+                "invalid duration '{}', units must be ordered from largest to smallest",
+                s
+            );
+        }
+        previous = Some(factor);
+
+        seconds = match value
+            .checked_mul(factor)
+            .and_then(|v| v.checked_add(seconds))
+        {
+            Some(v) => v,
+            None => bail!("invalid duration '{}', value is too large", s),
+        };
+    }
+
+    if !number.is_empty() {
+        bail!("invalid duration '{}', missing unit", s);
+    }
+    if seconds == 0 {
+        bail!("invalid duration '{}'", s);
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrape_config(yaml: &str) -> ScrapeConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn durations() {
+        for (input, expected) in [
+            ("15s", 15),
+            ("1m", 60),
+            ("1h30m", 5400),
+            ("1h30m15s", 5415),
+            ("2d12h", 216000),
+            ("1w", 604800),
+            ("1y", 31536000),
+            ("90s", 90),
+        ] {
+            assert_eq!(parse_duration(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for input in [
+            "",
+            "0s",
+            "15",
+            "s",
+            "1x",
+            "1.5s",
+            "-1s",
+            "500ms",
+            "1m500ms",
+            "1s1m",
+            "1m1m",
+            "1h 30m",
+            "99999999999999999999s",
+            "9999999999999y",
+        ] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn unsupported_settings_are_ignored() {
+        let sc = scrape_config(
+            "job_name: node\nhonor_labels: true\nscrape_interval: 1m30s\nstatic_configs:\n  - targets: ['host1:9100']\nfile_sd_configs:\n  - files: ['targets/*.yaml']\n    refresh_interval: 1m\n    unknown: 1\ntls_config:\n  ca_file: ca.crt\n  min_version: TLS12\n",
+        );
+        assert_eq!(
+            sc.unsupported.keys().collect::<Vec<&String>>(),
+            vec!["honor_labels"]
+        );
+
+        let job = convert(sc, Path::new("/etc/prometheus"), Some(60), Some(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.name, "node");
+        assert_eq!(job.interval, Some(90));
+        assert_eq!(job.timeout, Some(10));
+        assert_eq!(job.static_configs.len(), 1);
+        assert_eq!(
+            job.file_sd_configs[0].files,
+            vec!["/etc/prometheus/targets/*.yaml"]
+        );
+        assert_eq!(job.file_sd_configs[0].refresh_interval, 60);
+        assert_eq!(job.tls_config.unwrap().ca_file, "/etc/prometheus/ca.crt");
+        assert_eq!(job.metrics_path, constants::DEFAULT_SCRAPE_METRICS_PATH);
+    }
+
+    #[test]
+    fn jobs_without_supported_targets_are_skipped() {
+        let sc = scrape_config("job_name: k8s\nkubernetes_sd_configs:\n  - role: pod\n");
+        assert!(convert(sc, Path::new(""), None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn jobs_with_invalid_names_are_skipped() {
+        for name in ["''", "blackbox/http"] {
+            let sc = scrape_config(&format!(
+                "job_name: {}\nstatic_configs:\n  - targets: ['localhost:9100']\n",
+                name
+            ));
+            assert!(convert(sc, Path::new(""), None, None).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn invalid_jobs() {
+        for yaml in [
+            "job_name: node\nscrape_interval: 1h1d\nstatic_configs:\n  - targets: ['host1:9100']\n",
+            "job_name: node\nscrape_timeout: 100ms\nstatic_configs:\n  - targets: ['host1:9100']\n",
+            "job_name: node\nbearer_token: x\nauthorization:\n  credentials: y\nstatic_configs:\n  - targets: ['host1:9100']\n",
+        ] {
+            assert!(
+                convert(scrape_config(yaml), Path::new(""), None, None).is_err(),
+                "{}",
+                yaml
+            );
+        }
+    }
+
+    #[test]
+    fn import_jobs() {
+        let dir = std::env::temp_dir().join(format!(
+            "prometheus-mqtt-transport-test-import-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("prometheus.yml");
+        fs::write(
+            &file,
+            "global:\n  scrape_interval: 30s\n\
+             scrape_configs:\n\
+             - job_name: node\n  static_configs:\n    - targets: ['host1:9100']\n\
+             - job_name: sharded\n  static_configs:\n    - targets: ['host1:9100']\n  relabel_configs:\n    - source_labels: ['__address__']\n      target_label: __tmp_hash\n      modulus: 4\n      action: hashmod\n\
+             - job_name: unsupported_action\n  static_configs:\n    - targets: ['host1:9100']\n  relabel_configs:\n    - source_labels: ['a', 'b']\n      action: keepequal\n\
+             - job_name: not_selected\n  static_configs:\n    - targets: ['host1:9100']\n",
+        )
+        .unwrap();
+
+        let jobs = scrape_jobs(&config::Import {
+            jobs: vec![
+                "node".to_string(),
+                "sharded".to_string(),
+                "unsupported_action".to_string(),
+            ],
+            prometheus_config: file.to_string_lossy().to_string(),
+        });
+        fs::remove_dir_all(&dir).unwrap();
+
+        let jobs = jobs.unwrap();
+        assert_eq!(
+            jobs.iter().map(|j| j.name.as_str()).collect::<Vec<&str>>(),
+            vec!["node", "sharded"]
+        );
+        assert_eq!(jobs[0].interval, Some(30));
+    }
+}
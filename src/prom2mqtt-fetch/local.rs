@@ -0,0 +1,269 @@
+use crate::config;
+use crate::constants;
+use crate::massage;
+
+use log::{debug, error, warn};
+use simple_error::bail;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+// Read and merge all *.prom files of a directory like the textfile collector of the node_exporter.
+// Every file is parsed on its own, files that can't be read or parsed are skipped. The modification
+// times of the files and an error flag for skipped files are added as metrics
+pub fn textfile(directory: &str) -> Result<String, Box<dyn Error>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let entries = match fs::read_dir(directory) {
+        Ok(v) => v,
+        Err(e) => bail!("can't read directory {} - {}", directory, e),
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "prom") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut data = String::new();
+    let mut mtimes: Vec<String> = Vec::new();
+    let mut failed = 0;
+
+    for file in files.iter() {
+        let name = file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let content = match fs::read_to_string(file) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("can't read textfile {} - {}", file.display(), e);
+                failed = 1;
+                continue;
+            }
+        };
+        debug!(
+            "read {} bytes from textfile {}",
+            content.len(),
+            file.display()
+        );
+        if let Err(e) = massage::parse_scrape_data(&content, &name, &HashMap::new(), 0, None) {
+            warn!(
+                "can't parse textfile {}, skipping it - {}",
+                file.display(),
+                e
+            );
+            failed = 1;
+            continue;
+        }
+
+        data.push_str(&content);
+        if !content.is_empty() && !content.ends_with('\n') {
+            data.push('\n');
+        }
+
+        if let Ok(v) = fs::metadata(file).and_then(|m| m.modified()) {
+            let mtime = v
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default();
+            mtimes.push(format!(
+                "{}{{file=\"{}\"}} {}",
+                constants::TEXTFILE_MTIME_METRIC,
+                name,
+                mtime
+            ));
+        }
+    }
+
+    if !mtimes.is_empty() {
+        data.push_str(&format!(
+            "# HELP {} Unixtime mtime of textfiles successfully read.\n# TYPE {} gauge\n{}\n",
+            constants::TEXTFILE_MTIME_METRIC,
+            constants::TEXTFILE_MTIME_METRIC,
+            mtimes.join("\n")
+        ));
+    }
+    data.push_str(&format!(
+        "# HELP {} 1 if there was an error reading a textfile, 0 otherwise.\n# TYPE {} gauge\n{} {}\n",
+        constants::TEXTFILE_ERROR_METRIC,
+        constants::TEXTFILE_ERROR_METRIC,
+        constants::TEXTFILE_ERROR_METRIC,
+        failed
+    ));
+
+    Ok(data)
+}
+
+// Run a command and return its standard output. The command and all processes it started are killed if it
+// doesn't finish within timeout
+pub fn exec(cfg: &config::Exec, timeout: Duration) -> Result<String, Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+
+    debug!("running {} {:?}", cfg.command, cfg.args);
+    let mut child = match Command::new(&cfg.command)
+        .args(&cfg.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // own process group, so processes started by the command can be killed too
+        .process_group(0)
+        .spawn()
+    {
+        Ok(v) => v,
+        Err(e) => bail!("can't run {} - {}", cfg.command, e),
+    };
+
+    // the pipes are read while waiting, otherwise a command writing more than the pipe buffer would block
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let status = loop {
+        if let Some(v) = child.try_wait()? {
+            break v;
+        }
+        if Instant::now() >= deadline {
+            kill_group(&mut child);
+            bail!(
+                "{} didn't finish within {} seconds and has been killed",
+                cfg.command,
+                timeout.as_secs()
+            );
+        }
+        thread::sleep(Duration::from_millis(constants::EXEC_POLL_INTERVAL_MS));
+    };
+
+    // processes started in the background may still keep the pipes open after the command has exited
+    let stdout = match stdout.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(v) => v,
+        Err(_) => {
+            kill_group(&mut child);
+            bail!(
+                "standard output of {} hasn't been closed within {} seconds",
+                cfg.command,
+                timeout.as_secs()
+            );
+        }
+    };
+    let stderr = stderr
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+
+    if !status.success() {
+        bail!("{} failed with {} - {}", cfg.command, status, stderr);
+    }
+    if !stderr.is_empty() {
+        debug!("{} wrote to standard error: {}", cfg.command, stderr);
+    }
+
+    Ok(String::from_utf8(stdout)?)
+}
+
+fn kill_group(child: &mut std::process::Child) {
+    // SAFETY: kill(2) has no memory safety requirements, the negative PID addresses the process group
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// The data is sent when the pipe has been closed, the reading thread ends by itself once all writers are gone
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+    let (send, recv) = mpsc::channel();
+    thread::spawn(move || {
+        let mut data: Vec<u8> = Vec::new();
+        if let Some(mut p) = pipe {
+            let _ = p.read_to_end(&mut data);
+        }
+        let _ = send.send(data);
+    });
+    recv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "prometheus-mqtt-transport-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sh(script: &str) -> config::Exec {
+        config::Exec {
+            command: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+        }
+    }
+
+    #[test]
+    fn textfiles_are_parsed_separately() {
+        let dir = temp_dir("textfile");
+        fs::write(
+            dir.join("a.prom"),
+            "# generated by a cron job\n# HELP a_metric A metric.\n# TYPE a_metric gauge\na_metric{x=\"1\"} 1\n",
+        )
+        .unwrap();
+        fs::write(dir.join("b.prom"), "b_metric{x=\"1\" 2\n").unwrap();
+        fs::write(dir.join("c.prom"), "c_metric 3").unwrap();
+        fs::write(dir.join("ignored.txt"), "ignored 1\n").unwrap();
+
+        let data = textfile(&dir.to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        let data = data.unwrap();
+
+        assert!(data.contains("a_metric{x=\"1\"} 1\n"));
+        assert!(data.contains("c_metric 3\n"));
+        assert!(!data.contains("b_metric"));
+        assert!(!data.contains("ignored"));
+        assert!(data.contains(&format!(
+            "{}{{file=\"a.prom\"}}",
+            constants::TEXTFILE_MTIME_METRIC
+        )));
+        assert!(!data.contains("file=\"b.prom\""));
+        assert!(data.ends_with(&format!("{} 1\n", constants::TEXTFILE_ERROR_METRIC)));
+        assert!(massage::parse_scrape_data(&data, "t", &HashMap::new(), 0, None).is_ok());
+    }
+
+    #[test]
+    fn exec_output() {
+        let out = exec(&sh("echo 'up 1'; echo warning >&2"), Duration::from_secs(5)).unwrap();
+        assert_eq!(out, "up 1\n");
+
+        let e = exec(&sh("echo broken >&2; exit 3"), Duration::from_secs(5))
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("broken"), "{}", e);
+    }
+
+    #[test]
+    fn exec_timeout() {
+        let start = Instant::now();
+        assert!(exec(&sh("sleep 30"), Duration::from_secs(1)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn exec_background_process_keeps_stdout_open() {
+        let start = Instant::now();
+        let e = exec(&sh("sleep 30 & echo 'up 1'"), Duration::from_secs(1))
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("hasn't been closed"), "{}", e);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,15 +1,26 @@
 mod config;
 mod constants;
+mod data;
+mod discovery;
 mod exporter;
 mod http;
+mod import;
+mod local;
 mod massage;
 mod mqtt_sender;
+mod relabel;
+mod reload;
+mod schedule;
 mod scrape;
+mod unix;
 mod usage;
 
 use getopts::Options;
 use log::{debug, error, info};
-use std::sync::mpsc;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::{env, process};
 
@@ -17,9 +28,21 @@ fn main() {
     let argv: Vec<String> = env::args().collect();
     let mut options = Options::new();
     let mut log_level = log::LevelFilter::Info;
+    let mut cli_log_level: Option<log::LevelFilter> = None;
+    let mut cli_module_levels: Vec<(String, String)> = Vec::new();
 
     options.optflag("C", "check", "Check configuration file and exit");
+    options.optflag(
+        "",
+        "connect",
+        "Also check the connection to the MQTT broker when checking the configuration",
+    );
     options.optflag("D", "debug", "Enable debug logs");
+    options.optflag(
+        "",
+        "dry-run",
+        "Scrape all jobs once, print the payload and exit without connecting to MQTT",
+    );
     options.optflag("V", "version", "Show version information");
     options.optflag("h", "help", "Show help text");
     options.optopt(
@@ -28,6 +51,17 @@ fn main() {
         "Configuration file",
         constants::DEFAULT_CONFIG_FILE,
     );
+    options.optmulti(
+        "L",
+        "log-level",
+        "Set log level of a module",
+        "<module>=<level>",
+    );
+    options.optflag(
+        "",
+        "once",
+        "Scrape all jobs once, publish the data and exit",
+    );
     options.optflag("q", "quiet", "Quiet operation");
 
     let opts = match options.parse(&argv[1..]) {
@@ -52,12 +86,40 @@ fn main() {
 
     if opts.opt_present("D") {
         log_level = log::LevelFilter::Debug;
+        cli_log_level = Some(log_level);
     };
 
     if opts.opt_present("q") {
         log_level = log::LevelFilter::Warn;
+        cli_log_level = Some(log_level);
     };
 
+    for l in opts.opt_strs("L") {
+        match global::logging::parse_module_level(&l) {
+            Ok(v) => cli_module_levels.push(v),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                println!();
+                usage::show_usage();
+                process::exit(1);
+            }
+        };
+    }
+
+    if opts.opt_present("connect") && !opts.opt_present("C") {
+        eprintln!("Error: --connect can only be used together with --check");
+        println!();
+        usage::show_usage();
+        process::exit(1);
+    }
+
+    if opts.opt_present("once") && opts.opt_present("dry-run") {
+        eprintln!("Error: --once and --dry-run are mutually exclusive");
+        println!();
+        usage::show_usage();
+        process::exit(1);
+    }
+
     let config_file = match opts.opt_str("c") {
         Some(v) => v,
         None => constants::DEFAULT_CONFIG_FILE.to_string(),
@@ -77,38 +139,201 @@ fn main() {
     };
     if opts.opt_present("C") {
         info!("valid configuration file");
+        if opts.opt_present("connect") {
+            let mut report = global::check::Report::new();
+            global::check::mqtt(&configuration.mqtt, true, &mut report);
+            for s in discovery::Discovery::new()
+                .targets(&configuration, &[])
+                .iter()
+            {
+                report.add(
+                    &format!("scrape '{}' from {}", s.name, scrape::source_name(s)),
+                    scrape::check(s, configuration.global.timeout),
+                );
+            }
+            report.print();
+            if !report.passed() {
+                process::exit(1);
+            }
+        }
         process::exit(0);
     }
+
+    if let Err(e) = global::logging::configure(
+        &configuration.logging,
+        env!("CARGO_CRATE_NAME"),
+        cli_log_level,
+        &cli_module_levels,
+    ) {
+        error!("can't configure logging: {}", e);
+        process::exit(1);
+    }
     debug!("final configuration: {:?}", configuration);
 
     debug!("registering internal Prometheus metrics");
     exporter::register();
 
-    let (send, receive) = mpsc::channel::<Vec<u8>>();
+    if opts.opt_present("dry-run") {
+        match scrape::dry_run(&mut configuration) {
+            Ok(0) => process::exit(0),
+            Ok(v) => {
+                error!("{} scrapes failed", v);
+                process::exit(1);
+            }
+            Err(e) => {
+                error!("dry run failed: {}", e);
+                process::exit(1);
+            }
+        };
+    }
+
+    let health = Arc::new(global::health::Health::new());
+    let stop = Arc::new(AtomicBool::new(false));
+
+    if let Err(e) = global::shutdown::handle_signals(stop.clone()) {
+        error!("can't install signal handler: {}", e);
+        process::exit(1);
+    }
+
+    if opts.opt_present("once") {
+        process::exit(run_once(&mut configuration, health, stop));
+    }
+
+    let (send, receive) = mpsc::channel::<data::Data>();
+    let (reload_send, reload_receive) = mpsc::channel::<reload::Request>();
+    let (scrape_reload_send, scrape_reload_receive) = mpsc::channel::<config::Configuration>();
+    let (http_reload_send, http_reload_receive) = mpsc::channel::<config::Configuration>();
+
     let cfg = configuration.clone();
+    let mqtt_health = health.clone();
+    let mqtt_stop = stop.clone();
     debug!("spawning MQTT sender thread");
+    let mqtt_thread_id = thread::spawn(move || {
+        let result = mqtt_sender::run(&cfg, receive, mqtt_health, mqtt_stop.clone());
+        global::shutdown::worker_result(&mqtt_stop, result)
+    });
+
+    debug!("spawning configuration reload thread");
+    let cfg = configuration.clone();
+    let mqtt_reload_send = send.clone();
     thread::spawn(move || {
-        if let Err(e) = mqtt_sender::run(&cfg, receive) {
-            error!("can't start MQTT sender thread: {}", e);
+        // only fails if the other threads are gone
+        if let Err(e) = reload::run(
+            &config_file,
+            cfg,
+            reload_receive,
+            scrape_reload_send,
+            mqtt_reload_send,
+            http_reload_send,
+        ) {
+            debug!("configuration reload thread stopped: {}", e);
+        }
+    });
+
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("can't install signal handler: {}", e);
             process::exit(1);
         }
+    };
+    let rsc = reload_send.clone();
+    thread::spawn(move || {
+        for sig in signals.forever() {
+            info!("signal {} received, reloading configuration", sig);
+            // allow log rotation without restart
+            if let Err(e) = global::logging::reopen() {
+                error!("can't reopen log file: {}", e);
+            }
+            if let Err(e) = rsc.send(reload::Request { reply: None }) {
+                error!("can't send reload request: {}", e);
+            }
+        }
     });
 
     debug!("spawning HTTP server for internal Prometheus metrics");
     let cfg = configuration.clone();
-    thread::spawn(move || {
-        if let Err(e) = http::run(&cfg) {
-            error!(
-                "can't start HTTP server for internal Prometheus metrics: {}",
-                e
-            );
-            process::exit(1);
-        }
+    let http_stop = stop.clone();
+    let http_thread_id = thread::spawn(move || {
+        let result = http::run(
+            &cfg,
+            health,
+            reload_send,
+            http_reload_receive,
+            http_stop.clone(),
+        );
+        global::shutdown::worker_result(&http_stop, result)
     });
 
     // scrape loop
-    if let Err(e) = scrape::run(&mut configuration, send) {
-        error!("can't start scraping process: {}", e);
+    let mut ok = true;
+    if let Err(e) = scrape::run(
+        &mut configuration,
+        send.clone(),
+        scrape_reload_receive,
+        &stop,
+    ) {
+        error!("scraping process failed: {}", e);
+        stop.store(true, Ordering::SeqCst);
+        ok = false;
+    }
+
+    // Shutdown is queued behind all pending data, so the MQTT thread sends them first
+    if send.send(data::Data::Shutdown).is_err() {
+        debug!("MQTT sender thread already stopped");
+    }
+    ok &= global::shutdown::join("MQTT sender", mqtt_thread_id);
+    ok &= global::shutdown::join("HTTP server", http_thread_id);
+
+    if !ok {
         process::exit(1);
     }
+    info!("shutdown complete");
+}
+
+// Scrape all jobs once, publish the data and return the exit code
+fn run_once(
+    configuration: &mut config::Configuration,
+    health: Arc<global::health::Health>,
+    stop: Arc<AtomicBool>,
+) -> i32 {
+    let (send, receive) = mpsc::channel::<data::Data>();
+
+    let cfg = configuration.clone();
+    let mqtt_health = health.clone();
+    let mqtt_stop = stop.clone();
+    debug!("spawning MQTT sender thread");
+    let mqtt_thread_id = thread::spawn(move || {
+        let result = mqtt_sender::run(&cfg, receive, mqtt_health, mqtt_stop.clone());
+        global::shutdown::worker_result(&mqtt_stop, result)
+    });
+
+    let mut ok = match scrape::once(configuration, send.clone()) {
+        Ok(0) => true,
+        Ok(v) => {
+            error!("{} scrapes failed", v);
+            false
+        }
+        Err(e) => {
+            error!("scraping process failed: {}", e);
+            false
+        }
+    };
+
+    if send.send(data::Data::Shutdown).is_err() {
+        debug!("MQTT sender thread already stopped");
+    }
+    ok &= global::shutdown::join("MQTT sender", mqtt_thread_id);
+
+    // the MQTT thread only logs failed publish attempts
+    if !health.is_operational() {
+        error!("data has not been published to the MQTT broker");
+        ok = false;
+    }
+
+    if ok {
+        0
+    } else {
+        1
+    }
 }
@@ -1,10 +1,12 @@
+use crate::constants;
 use crate::exporter;
+use crate::relabel;
 
 use flate2::read::GzEncoder;
 use flate2::Compression;
 use log::{debug, info};
 use simple_error::bail;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::io::prelude::*;
 
@@ -13,40 +15,40 @@ pub fn parse_scrape_data(
     name: &str,
     labels: &HashMap<String, String>,
     expiration: i64,
+    relabeler: Option<&relabel::Relabeler>,
 ) -> Result<global::payload::Message, Box<dyn Error>> {
     let mut message = global::payload::Message {
         name: name.to_string(),
         expiration,
+        failed: false,
         payload: Vec::<global::payload::Payload>::new(),
     };
-    let mut metrics: HashMap<&str, global::payload::Payload> = HashMap::new();
+    let mut metrics: HashMap<String, global::payload::Payload> = HashMap::new();
 
     for raw_line in raw.lines() {
         let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
 
         if line.starts_with('#') {
             let fields: Vec<&str> = line.split_ascii_whitespace().collect();
-            // Prometheus HELP/TYPE fields have *at least* four fields
-            if fields.len() < 4 {
+
+            // other lines starting with # are comments
+            let ftype = match fields.get(1) {
+                Some(&"HELP") | Some(&"TYPE") if fields[0] == "#" => fields[1],
+                _ => continue,
+            };
+            // the docstring of HELP may be empty, TYPE always needs a type
+            if fields.len() < 3 || (ftype == "TYPE" && fields.len() < 4) {
                 bail!(
                     "malformed Prometheus metric line: too few fields in \"{}\"",
                     raw_line
                 );
             }
-
-            // Check field type
-            let ftype = match fields[1] {
-                "HELP" | "TYPE" => fields[1],
-                _ => {
-                    bail!(
-                        "malformed Prometheus metric line: invalid type {}",
-                        fields[1]
-                    );
-                }
-            };
             let mname = fields[2];
             let fdata = fields[3..].join(" ");
-            let entry = metrics.entry(mname).or_default();
+            let entry = metrics.entry(mname.to_string()).or_default();
             entry.metric_name = mname.to_string();
 
             if ftype == "HELP" {
@@ -54,19 +56,41 @@ pub fn parse_scrape_data(
             } else {
                 entry.data_type = fdata;
             }
+        } else if let Some(r) = relabeler {
+            if let Some((mname, sample)) = relabel_sample(line, labels, r)? {
+                let entry = metrics.entry(mname.clone()).or_default();
+                entry.metric_name = mname;
+                entry.data.push(sample);
+            }
         } else {
-            let mvec: Vec<&str> = if line.contains('{') {
-                line.splitn(2, '{').collect()
-            } else {
-                line.splitn(2, ' ').collect()
-            };
-            let mname = mvec[0];
-            let entry = metrics.entry(mname).or_default();
-            entry.metric_name = mname.to_string();
+            let mname = parse_sample(line)?.name;
+            let entry = metrics.entry(mname.clone()).or_default();
+            entry.metric_name = mname;
             entry.data.push(add_labels(line, labels));
         }
     }
 
+    // all samples of a metric may have been dropped by metric relabeling. Samples of histograms and summaries
+    // are stored as <name>_bucket, <name>_sum and <name>_count, so HELP and TYPE are kept as long as any
+    // sample of the family is left
+    let emptied: Vec<String> = match relabeler {
+        Some(_) => metrics
+            .keys()
+            .filter(|name| {
+                !["", "_bucket", "_sum", "_count"].iter().any(|suffix| {
+                    metrics
+                        .get(&format!("{}{}", name, suffix))
+                        .is_some_and(|m| !m.data.is_empty())
+                })
+            })
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    };
+    for name in emptied {
+        metrics.remove(&name);
+    }
+
     for (_, value) in metrics {
         message.payload.push(value);
     }
@@ -74,6 +98,185 @@ pub fn parse_scrape_data(
     Ok(message)
 }
 
+// Add the Prometheus style series up, scrape_duration_seconds, scrape_samples_scraped and scrape_body_size_bytes
+// and the number of retries as scrape_retries, the number of samples is counted before the series are added
+pub fn add_scrape_metrics(
+    msg: &mut global::payload::Message,
+    labels: &HashMap<String, String>,
+    up: bool,
+    duration: f64,
+    body_size: usize,
+    retries: u32,
+) {
+    let samples: usize = msg.payload.iter().map(|p| p.data.len()).sum();
+    let series = [
+        (
+            constants::SYNTHETIC_UP_NAME,
+            constants::SYNTHETIC_UP_HELP,
+            if up { "1".to_string() } else { "0".to_string() },
+        ),
+        (
+            constants::SYNTHETIC_SCRAPE_DURATION_NAME,
+            constants::SYNTHETIC_SCRAPE_DURATION_HELP,
+            duration.to_string(),
+        ),
+        (
+            constants::SYNTHETIC_SCRAPE_SAMPLES_NAME,
+            constants::SYNTHETIC_SCRAPE_SAMPLES_HELP,
+            samples.to_string(),
+        ),
+        (
+            constants::SYNTHETIC_SCRAPE_BODY_SIZE_NAME,
+            constants::SYNTHETIC_SCRAPE_BODY_SIZE_HELP,
+            body_size.to_string(),
+        ),
+        (
+            constants::SYNTHETIC_SCRAPE_RETRIES_NAME,
+            constants::SYNTHETIC_SCRAPE_RETRIES_HELP,
+            retries.to_string(),
+        ),
+    ];
+
+    for (name, help, value) in series {
+        msg.payload.push(global::payload::Payload {
+            data: vec![add_labels(&format!("{} {}", name, value), labels)],
+            data_type: "gauge".to_string(),
+            help: help.to_string(),
+            metric_name: name.to_string(),
+        });
+    }
+}
+
+// Message sent for a failed scrape, it only contains the synthetic series with up set to 0
+pub fn failed_scrape_message(
+    name: &str,
+    labels: &HashMap<String, String>,
+    expiration: i64,
+    duration: f64,
+    retries: u32,
+) -> global::payload::Message {
+    let mut msg = global::payload::Message {
+        name: name.to_string(),
+        expiration,
+        failed: false,
+        payload: Vec::new(),
+    };
+    add_scrape_metrics(&mut msg, labels, false, duration, 0, retries);
+    msg
+}
+
+// Replace the synthetic series of msg by the synthetic series of another message
+pub fn replace_scrape_metrics(msg: &mut global::payload::Message, from: global::payload::Message) {
+    let synthetic = [
+        constants::SYNTHETIC_UP_NAME,
+        constants::SYNTHETIC_SCRAPE_DURATION_NAME,
+        constants::SYNTHETIC_SCRAPE_SAMPLES_NAME,
+        constants::SYNTHETIC_SCRAPE_BODY_SIZE_NAME,
+        constants::SYNTHETIC_SCRAPE_RETRIES_NAME,
+    ];
+    msg.payload
+        .retain(|p| !synthetic.contains(&p.metric_name.as_str()));
+    msg.payload.extend(
+        from.payload
+            .into_iter()
+            .filter(|p| synthetic.contains(&p.metric_name.as_str())),
+    );
+}
+
+// Convert data in OpenMetrics format into the Prometheus text format. Counters are named after their _total series,
+// _created series, units, exemplars and # EOF are dropped and timestamps are converted from seconds to milliseconds
+pub fn openmetrics_to_text(raw: &str) -> String {
+    let mut types: HashMap<&str, &str> = HashMap::new();
+    for line in raw.lines() {
+        let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+        if fields.len() >= 4 && fields[0] == "#" && fields[1] == "TYPE" {
+            types.insert(fields[2], fields[3]);
+        }
+    }
+
+    let mut result = String::new();
+    for raw_line in raw.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "# EOF" {
+            break;
+        }
+
+        if let Some(comment) = line.strip_prefix('#') {
+            let fields: Vec<&str> = comment.trim_start().splitn(3, ' ').collect();
+            if fields.len() < 3 || (fields[0] != "HELP" && fields[0] != "TYPE") {
+                // UNIT has no equivalent in the text format
+                continue;
+            }
+            let mtype = types.get(fields[1]).copied().unwrap_or_default();
+            let mname = match mtype {
+                "counter" => format!("{}_total", fields[1]),
+                "info" => format!("{}_info", fields[1]),
+                _ => fields[1].to_string(),
+            };
+            let data = if fields[0] == "TYPE" {
+                match mtype {
+                    "counter" | "gauge" | "histogram" | "summary" => mtype,
+                    "info" | "stateset" => "gauge",
+                    _ => "untyped",
+                }
+            } else {
+                fields[2]
+            };
+            result.push_str(&format!("# {} {} {}\n", fields[0], mname, data));
+            continue;
+        }
+
+        // the label set ends at the first closing brace outside of a quoted label value
+        let mut end = line
+            .find(|c: char| c == '{' || c.is_ascii_whitespace())
+            .unwrap_or(line.len());
+        let name = &line[..end];
+        if line[end..].starts_with('{') {
+            let mut quoted = false;
+            let mut escaped = false;
+            for (pos, c) in line[end..].char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => quoted = !quoted,
+                    '}' if !quoted => {
+                        end += pos + 1;
+                        break;
+                    }
+                    _ => {}
+                };
+            }
+        }
+
+        if let Some(base) = name.strip_suffix("_created") {
+            if matches!(
+                types.get(base).copied(),
+                Some("counter" | "histogram" | "summary" | "gaugehistogram")
+            ) {
+                continue;
+            }
+        }
+
+        // value and timestamp, an exemplar starts with #
+        let rest = line[end..].split('#').next().unwrap_or_default();
+        let mut fields = rest.split_ascii_whitespace();
+        let value = fields.next().unwrap_or_default();
+        match fields.next().and_then(|t| t.parse::<f64>().ok()) {
+            Some(t) => result.push_str(&format!(
+                "{} {} {}\n",
+                &line[..end],
+                value,
+                (t * 1000.0).round() as i64
+            )),
+            None => result.push_str(&format!("{} {}\n", &line[..end], value)),
+        };
+    }
+    result
+}
+
 fn add_labels(data: &str, l: &HashMap<String, String>) -> String {
     if l.is_empty() {
         return data.to_string();
@@ -84,13 +287,129 @@ fn add_labels(data: &str, l: &HashMap<String, String>) -> String {
         let splitted: Vec<&str> = data.splitn(2, '{').collect();
         format!("{}{{{},{}", splitted[0], flat.join(","), splitted[1])
     } else {
-        let splitted: Vec<&str> = data.splitn(2, ' ').collect();
-        format!("{}{{{}}} {}", splitted[0], flat.join(","), splitted[1])
+        // the value may be separated by any whitespace
+        let end = data
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(data.len());
+        format!("{}{{{}}}{}", &data[..end], flat.join(","), &data[end..])
     };
 
     result
 }
 
+// Apply metric relabeling to a sample, the labels of the target replace scraped labels with the same name.
+// Returns the metric name and the rewritten sample or None if the sample has been dropped
+fn relabel_sample(
+    line: &str,
+    l: &HashMap<String, String>,
+    relabeler: &relabel::Relabeler,
+) -> Result<Option<(String, String)>, Box<dyn Error>> {
+    let Sample {
+        name,
+        mut labels,
+        value,
+    } = parse_sample(line)?;
+    labels.insert("__name__".to_string(), name);
+    labels.extend(l.clone());
+
+    let mut labels = match relabeler.apply(labels) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let name = labels.remove("__name__").unwrap_or_default();
+    if name.is_empty() {
+        return Ok(None);
+    }
+    labels.retain(|k, _| !k.starts_with("__"));
+
+    if labels.is_empty() {
+        return Ok(Some((name.clone(), format!("{} {}", name, value))));
+    }
+    let sorted: BTreeMap<String, String> = labels.into_iter().collect();
+    let flat: Vec<String> = sorted
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    Ok(Some((
+        name.clone(),
+        format!("{}{{{}}} {}", name, flat.join(","), value),
+    )))
+}
+
+// A single sample, labels contain unescaped values and value includes an optional timestamp
+struct Sample {
+    name: String,
+    labels: HashMap<String, String>,
+    value: String,
+}
+
+fn parse_sample(line: &str) -> Result<Sample, Box<dyn Error>> {
+    let mut labels: HashMap<String, String> = HashMap::new();
+
+    let end = line
+        .find(|c: char| c == '{' || c.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let name = line[..end].to_string();
+    let mut rest = &line[end..];
+
+    if let Some(l) = rest.strip_prefix('{') {
+        let mut chars = l.char_indices();
+        let mut label = String::new();
+        let mut closed = false;
+
+        while let Some((pos, c)) = chars.next() {
+            match c {
+                '}' => {
+                    rest = &l[pos + 1..];
+                    closed = true;
+                    break;
+                }
+                ',' | ' ' | '\t' => {}
+                '=' => {
+                    if chars.next().map(|(_, c)| c) != Some('"') {
+                        bail!("malformed Prometheus metric line: \"{}\"", line);
+                    }
+                    let mut value = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, 'n')) => value.push('\n'),
+                                Some((_, c)) => value.push(c),
+                                None => bail!("malformed Prometheus metric line: \"{}\"", line),
+                            },
+                            Some((_, c)) => value.push(c),
+                            None => bail!("malformed Prometheus metric line: \"{}\"", line),
+                        };
+                    }
+                    labels.insert(label.trim().to_string(), value);
+                    label.clear();
+                }
+                _ => label.push(c),
+            };
+        }
+        if !closed {
+            bail!("malformed Prometheus metric line: \"{}\"", line);
+        }
+    }
+
+    let value = rest.trim();
+    if name.is_empty() || value.is_empty() {
+        bail!("malformed Prometheus metric line: \"{}\"", line);
+    }
+    Ok(Sample {
+        name,
+        labels,
+        value: value.to_string(),
+    })
+}
+
+fn escape_label_value(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 fn flatten_label_map(l: &HashMap<String, String>) -> Vec<String> {
     let mut result: Vec<String> = Vec::new();
 
@@ -105,7 +424,7 @@ pub fn build_mqtt_message(
     msg: &Vec<global::payload::Message>,
     compress: bool,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    let payload_str = serde_json::to_string(&msg)?;
+    let payload_str = payload_json(msg)?;
     let payload: Vec<u8>;
 
     exporter::SIZE.set(payload_str.len() as i64);
@@ -140,9 +459,236 @@ pub fn build_mqtt_message(
     Ok(payload)
 }
 
-fn compress_data(s: String) -> Result<Vec<u8>, Box<dyn Error>> {
+// JSON payload before compression
+pub fn payload_json(msg: &Vec<global::payload::Message>) -> Result<String, Box<dyn Error>> {
+    Ok(serde_json::to_string(&msg)?)
+}
+
+pub fn compress_data(s: String) -> Result<Vec<u8>, Box<dyn Error>> {
     let mut gzencoded = GzEncoder::new(s.as_bytes(), Compression::best());
     let mut compressed = Vec::<u8>::new();
     gzencoded.read_to_end(&mut compressed)?;
     Ok(compressed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> HashMap<String, String> {
+        HashMap::from([("dc".to_string(), "a".to_string())])
+    }
+
+    fn metric<'a>(msg: &'a global::payload::Message, name: &str) -> &'a global::payload::Payload {
+        msg.payload.iter().find(|p| p.metric_name == name).unwrap()
+    }
+
+    #[test]
+    fn comments_and_empty_lines() {
+        let msg = parse_scrape_data(
+            "# generated by a cron job\n#no space\n\n# HELP node_load1 1m load average.\n# TYPE node_load1 gauge\nnode_load1 0.5\n# HELP no_help\nno_help\t2\n",
+            "node",
+            &labels(),
+            60,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(msg.payload.len(), 2);
+        let load = metric(&msg, "node_load1");
+        assert_eq!(load.help, "1m load average.");
+        assert_eq!(load.data_type, "gauge");
+        assert_eq!(load.data, vec!["node_load1{dc=\"a\"} 0.5"]);
+        let no_help = metric(&msg, "no_help");
+        assert_eq!(no_help.help, "");
+        assert_eq!(no_help.data, vec!["no_help{dc=\"a\"}\t2"]);
+    }
+
+    #[test]
+    fn malformed_data() {
+        for raw in [
+            "# TYPE node_load1\n",
+            "# HELP\n",
+            "node_load1\n",
+            "node_load1{x=\"1\" 1\n",
+            "{x=\"1\"} 1\n",
+        ] {
+            assert!(
+                parse_scrape_data(raw, "node", &labels(), 60, None).is_err(),
+                "{:?}",
+                raw
+            );
+        }
+    }
+
+    // Example from the OpenMetrics specification, as exposed by a Python client
+    const OPENMETRICS: &str = r#"# TYPE acme_http_router_request_seconds summary
+# UNIT acme_http_router_request_seconds seconds
+# HELP acme_http_router_request_seconds Latency though all of ACME's HTTP request router.
+acme_http_router_request_seconds_sum{path="/api/v1",method="GET"} 9036.32
+acme_http_router_request_seconds_count{path="/api/v1",method="GET"} 807283.0
+acme_http_router_request_seconds_created{path="/api/v1",method="GET"} 1605281325.0
+# TYPE go_goroutines gauge
+# HELP go_goroutines Number of goroutines that currently exist.
+go_goroutines 69
+# TYPE process_cpu_seconds counter
+# UNIT process_cpu_seconds seconds
+# HELP process_cpu_seconds Total user and system CPU time spent in seconds.
+process_cpu_seconds_total 4.20072246e+06
+process_cpu_seconds_created 1605281325.0
+# TYPE http_requests counter
+# HELP http_requests Requests by path.
+http_requests_total{path="/a{b}",code="200"} 17 1520879607.789 # {trace_id="KOO5S4vxi0o"} 0.67
+http_requests_created{path="/a{b}",code="200"} 1520430000.123
+# TYPE foo histogram
+foo_bucket{le="0.1"} 8 # {id="abc"} 0.067 1520879607.7
+foo_bucket{le="+Inf"} 17
+foo_count 17
+foo_sum 324789.3
+foo_created 1520430000.123
+# TYPE python info
+# HELP python Python platform information.
+python_info{implementation="CPython",major="3",minor="11"} 1
+# TYPE entity stateset
+# HELP entity Phase of the entity.
+entity{entity="replicaset",phase="running"} 1
+entity{entity="replicaset",phase="stopped"} 0
+# TYPE mystery unknown
+mystery 1
+# EOF
+after_eof 1
+"#;
+
+    #[test]
+    fn openmetrics_conversion() {
+        let expected = r#"# TYPE acme_http_router_request_seconds summary
+# HELP acme_http_router_request_seconds Latency though all of ACME's HTTP request router.
+acme_http_router_request_seconds_sum{path="/api/v1",method="GET"} 9036.32
+acme_http_router_request_seconds_count{path="/api/v1",method="GET"} 807283.0
+# TYPE go_goroutines gauge
+# HELP go_goroutines Number of goroutines that currently exist.
+go_goroutines 69
+# TYPE process_cpu_seconds_total counter
+# HELP process_cpu_seconds_total Total user and system CPU time spent in seconds.
+process_cpu_seconds_total 4.20072246e+06
+# TYPE http_requests_total counter
+# HELP http_requests_total Requests by path.
+http_requests_total{path="/a{b}",code="200"} 17 1520879607789
+# TYPE foo histogram
+foo_bucket{le="0.1"} 8
+foo_bucket{le="+Inf"} 17
+foo_count 17
+foo_sum 324789.3
+# TYPE python_info gauge
+# HELP python_info Python platform information.
+python_info{implementation="CPython",major="3",minor="11"} 1
+# TYPE entity gauge
+# HELP entity Phase of the entity.
+entity{entity="replicaset",phase="running"} 1
+entity{entity="replicaset",phase="stopped"} 0
+# TYPE mystery untyped
+mystery 1
+"#;
+        assert_eq!(openmetrics_to_text(OPENMETRICS), expected);
+    }
+
+    #[test]
+    fn converted_openmetrics_can_be_parsed() {
+        let msg = parse_scrape_data(
+            &openmetrics_to_text(OPENMETRICS),
+            "python",
+            &labels(),
+            60,
+            None,
+        )
+        .unwrap();
+
+        let cpu = metric(&msg, "process_cpu_seconds_total");
+        assert_eq!(cpu.data_type, "counter");
+        assert_eq!(cpu.help, "Total user and system CPU time spent in seconds.");
+        assert_eq!(
+            cpu.data,
+            vec!["process_cpu_seconds_total{dc=\"a\"} 4.20072246e+06"]
+        );
+        let info = metric(&msg, "python_info");
+        assert_eq!(info.data_type, "gauge");
+        assert_eq!(info.data.len(), 1);
+        let entity = metric(&msg, "entity");
+        assert_eq!(entity.data_type, "gauge");
+        assert_eq!(entity.data.len(), 2);
+        assert!(msg.payload.iter().all(|p| p.metric_name != "after_eof"));
+        assert!(msg
+            .payload
+            .iter()
+            .flat_map(|p| p.data.iter())
+            .all(|d| !d.contains("_created") && !d.contains('#')));
+    }
+
+    #[test]
+    fn relabeling_keeps_histogram_and_summary_metadata() {
+        let raw = "# HELP http_request_duration_seconds Request duration.
+# TYPE http_request_duration_seconds histogram
+http_request_duration_seconds_bucket{le=\"0.1\"} 8
+http_request_duration_seconds_bucket{le=\"+Inf\"} 17
+http_request_duration_seconds_sum 3.5
+http_request_duration_seconds_count 17
+# HELP rpc_duration_seconds RPC duration.
+# TYPE rpc_duration_seconds summary
+rpc_duration_seconds{quantile=\"0.5\"} 0.2
+rpc_duration_seconds_sum 12.5
+rpc_duration_seconds_count 60
+# HELP go_gc_duration_seconds GC duration.
+# TYPE go_gc_duration_seconds summary
+go_gc_duration_seconds_sum 0.1
+go_gc_duration_seconds_count 4
+# HELP debug_requests_total Debug requests.
+# TYPE debug_requests_total counter
+debug_requests_total 3
+";
+        let rules: Vec<relabel::RelabelConfig> = serde_yaml::from_str(
+            "- source_labels: [__name__]\n  regex: 'debug_.*|go_gc_.*'\n  action: drop\n",
+        )
+        .unwrap();
+        let relabeler = relabel::Relabeler::new(&rules).unwrap();
+        let msg = parse_scrape_data(raw, "node", &labels(), 60, Some(&relabeler)).unwrap();
+
+        let histogram = metric(&msg, "http_request_duration_seconds");
+        assert_eq!(histogram.data_type, "histogram");
+        assert_eq!(histogram.help, "Request duration.");
+        assert!(histogram.data.is_empty());
+        assert_eq!(
+            metric(&msg, "http_request_duration_seconds_bucket")
+                .data
+                .len(),
+            2
+        );
+        assert_eq!(
+            metric(&msg, "http_request_duration_seconds_sum").data.len(),
+            1
+        );
+        assert_eq!(
+            metric(&msg, "http_request_duration_seconds_count")
+                .data
+                .len(),
+            1
+        );
+
+        let summary = metric(&msg, "rpc_duration_seconds");
+        assert_eq!(summary.data_type, "summary");
+        assert_eq!(summary.data.len(), 1);
+
+        // families without any sample left are removed together with their metadata
+        for name in [
+            "go_gc_duration_seconds",
+            "go_gc_duration_seconds_sum",
+            "debug_requests_total",
+        ] {
+            assert!(
+                msg.payload.iter().all(|p| p.metric_name != name),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(msg.payload.len(), 7);
+    }
+}
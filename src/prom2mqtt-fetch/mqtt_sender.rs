@@ -1,49 +1,84 @@
 use crate::config;
+use crate::data;
 use crate::exporter;
 
 use log::{debug, error, info, warn};
 use std::error::Error;
-use std::sync::mpsc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
 use std::{thread, time};
 
 pub fn run(
     cfg: &config::Configuration,
-    receiver: mpsc::Receiver<Vec<u8>>,
+    receiver: mpsc::Receiver<data::Data>,
+    health: Arc<global::health::Health>,
+    stop: Arc<AtomicBool>,
 ) -> Result<(), Box<dyn Error>> {
-    let one_second = time::Duration::from_secs(1);
-
-    debug!("creating MQTT connection");
-    let mqtt_conn_opts = global::mqtt::connection_builder(&cfg.mqtt)?;
+    let mut mqtt_cfg = cfg.mqtt.clone();
+    let mut mqtt_client = match connect(&mqtt_cfg, &health, &stop)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    let mut deadline: Option<time::Instant> = None;
 
-    debug!("creating MQTT client");
-    let mqtt_client = global::mqtt::client_builder(&cfg.mqtt)?;
-
-    info!("connecting to MQTT broker {}", cfg.mqtt.broker);
-    let mut ticktock: u64 = 0;
     loop {
-        let mco = mqtt_conn_opts.clone();
-        if let Err(e) = mqtt_client.connect(mco) {
-            error!(
-                "connection to MQTT broker {} failed: {}",
-                cfg.mqtt.broker, e
-            );
-            if ticktock > cfg.mqtt.reconnect_timeout {
-                return Err(Box::new(e));
+        let data = match receiver.recv()? {
+            data::Data::Payload(v) => v,
+            data::Data::Shutdown => {
+                info!("disconnecting from MQTT broker {}", mqtt_cfg.broker);
+                if let Err(e) = mqtt_client.disconnect(None) {
+                    warn!("can't disconnect from MQTT broker - {}", e);
+                }
+                health.set_connected(false);
+                return Ok(());
+            }
+            data::Data::Reload(v) => {
+                if global::mqtt::connection_changed(&mqtt_cfg, &v) {
+                    // the current connection is only replaced if the new broker can be reached
+                    info!(
+                        "MQTT connection settings changed, connecting to {}",
+                        v.broker
+                    );
+                    let client = match connect(&v, &health, &stop) {
+                        Ok(Some(c)) => c,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            error!(broker = v.broker.as_str();
+                                "can't connect to MQTT broker {}, keeping the connection to {} - {}",
+                                v.broker,
+                                mqtt_cfg.broker,
+                                e
+                            );
+                            health.set_connected(mqtt_client.is_connected());
+                            continue;
+                        }
+                    };
+                    info!("disconnecting from MQTT broker {}", mqtt_cfg.broker);
+                    if let Err(e) = mqtt_client.disconnect(None) {
+                        warn!("can't disconnect from MQTT broker - {}", e);
+                    }
+                    mqtt_client = client;
+                }
+                mqtt_cfg = v;
+                continue;
+            }
+        };
+
+        // pending data is still send on shutdown, but only until the deadline is reached
+        if global::shutdown::requested(&stop) {
+            let until = *deadline.get_or_insert_with(|| {
+                time::Instant::now()
+                    + time::Duration::from_secs(global::constants::SHUTDOWN_TIMEOUT)
+            });
+            if time::Instant::now() > until {
+                warn!(
+                    "shutdown timeout exceeded, dropping {} bytes of pending data",
+                    data.len()
+                );
+                continue;
             }
-            thread::sleep(one_second);
-            ticktock += 1;
-            warn!(
-                "retryingo to connect to MQTT broker {} - attempt {}/{}",
-                cfg.mqtt.broker, ticktock, cfg.mqtt.reconnect_timeout
-            );
-        } else {
-            info!("connected to MQTT broker {}", cfg.mqtt.broker);
-            break;
         }
-    }
 
-    loop {
-        let data = receiver.recv()?;
         // XXX: Shouldn't happen but just to make sure
         if data.len() < 2 {
             error!("received data is too short ({} bytes)", data.len());
@@ -53,35 +88,84 @@ pub fn run(
         if !mqtt_client.is_connected() {
             warn!(
                 "connection to MQTT broker {} lost, reconnecting",
-                cfg.mqtt.broker
+                mqtt_cfg.broker
             );
+            health.set_connected(false);
             if let Err(e) = mqtt_client.reconnect() {
-                error!(
+                error!(broker = mqtt_cfg.broker.as_str();
                     "reconnection to MQTT broker {} failed - {}",
-                    cfg.mqtt.broker, e
+                    mqtt_cfg.broker, e
                 );
                 continue;
             }
+            health.set_connected(true);
         }
 
         let pubt = std::time::Instant::now();
-        info!(
+        let bytes = data.len();
+        info!(broker = mqtt_cfg.broker.as_str(), topic = mqtt_cfg.topic.as_str(), bytes = bytes;
             "sending {} bytes of data to topic {} on {}",
             data.len(),
-            &cfg.mqtt.topic,
-            &cfg.mqtt.broker
+            &mqtt_cfg.topic,
+            &mqtt_cfg.broker
         );
-        let msg = paho_mqtt::message::Message::new(&cfg.mqtt.topic, data, cfg.mqtt.qos);
+        let msg = paho_mqtt::message::Message::new(&mqtt_cfg.topic, data, mqtt_cfg.qos);
         if let Err(e) = mqtt_client.publish(msg) {
-            error!("sending message to MQTT broker failed - {}", e);
+            error!(broker = mqtt_cfg.broker.as_str(), topic = mqtt_cfg.topic.as_str(), bytes = bytes; "sending message to MQTT broker failed - {}", e);
             exporter::MQTT_SUCCESS.set(0);
+            health.set_connected(mqtt_client.is_connected());
             continue;
         }
         let pubt_elapsed = pubt.elapsed().as_secs_f64();
         exporter::MQTT_SEND_TIME.observe(pubt_elapsed);
-        exporter::MQTT_QOS.set(cfg.mqtt.qos as i64);
+        exporter::MQTT_QOS.set(mqtt_cfg.qos as i64);
         exporter::MQTT_SUCCESS.set(1);
+        health.set_operational(true);
 
-        info!("MQTT message send in {} seconds", pubt_elapsed,);
+        info!(broker = mqtt_cfg.broker.as_str(), topic = mqtt_cfg.topic.as_str(), bytes = bytes, duration = pubt_elapsed;
+            "MQTT message send in {} seconds", pubt_elapsed,
+        );
     }
 }
+
+// Returns None if a shutdown was requested while connecting
+fn connect(
+    cfg: &global::mqtt::MQTT,
+    health: &global::health::Health,
+    stop: &AtomicBool,
+) -> Result<Option<paho_mqtt::Client>, Box<dyn Error>> {
+    let one_second = time::Duration::from_secs(1);
+
+    debug!("creating MQTT connection");
+    let mqtt_conn_opts = global::mqtt::connection_builder(cfg)?;
+
+    debug!("creating MQTT client");
+    let mqtt_client = global::mqtt::client_builder(cfg)?;
+
+    info!(broker = cfg.broker.as_str(); "connecting to MQTT broker {}", cfg.broker);
+    let mut ticktock: u64 = 0;
+    loop {
+        let mco = mqtt_conn_opts.clone();
+        if let Err(e) = mqtt_client.connect(mco) {
+            error!(broker = cfg.broker.as_str(); "connection to MQTT broker {} failed: {}", cfg.broker, e);
+            if ticktock > cfg.reconnect_timeout {
+                return Err(Box::new(e));
+            }
+            if global::shutdown::requested(stop) {
+                return Ok(None);
+            }
+            thread::sleep(one_second);
+            ticktock += 1;
+            warn!(
+                "retryingo to connect to MQTT broker {} - attempt {}/{}",
+                cfg.broker, ticktock, cfg.reconnect_timeout
+            );
+        } else {
+            info!(broker = cfg.broker.as_str(); "connected to MQTT broker {}", cfg.broker);
+            health.set_connected(true);
+            break;
+        }
+    }
+
+    Ok(Some(mqtt_client))
+}
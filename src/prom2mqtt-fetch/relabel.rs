@@ -0,0 +1,324 @@
+use crate::constants;
+
+use openssl::hash::{hash, MessageDigest};
+use regex::Regex;
+use serde::Deserialize;
+use simple_error::bail;
+use std::collections::HashMap;
+use std::error::Error;
+
+// Relabeling rule in the format of Prometheus relabel_configs
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RelabelConfig {
+    #[serde(default)]
+    pub action: Action,
+    #[serde(default)]
+    pub modulus: u64,
+    #[serde(default = "default_regex")]
+    pub regex: String,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+    #[serde(default = "default_separator")]
+    pub separator: String,
+    #[serde(default)]
+    pub source_labels: Vec<String>,
+    #[serde(default)]
+    pub target_label: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Replace,
+    Keep,
+    Drop,
+    Labelmap,
+    Labeldrop,
+    Labelkeep,
+    Lowercase,
+    Uppercase,
+    Hashmod,
+}
+
+fn default_regex() -> String {
+    constants::DEFAULT_RELABEL_REGEX.to_string()
+}
+
+fn default_replacement() -> String {
+    constants::DEFAULT_RELABEL_REPLACEMENT.to_string()
+}
+
+fn default_separator() -> String {
+    constants::DEFAULT_RELABEL_SEPARATOR.to_string()
+}
+
+// Relabeling rules with compiled regular expressions
+#[derive(Debug)]
+pub struct Relabeler {
+    rules: Vec<(RelabelConfig, Regex)>,
+}
+
+impl Relabeler {
+    pub fn new(cfg: &[RelabelConfig]) -> Result<Self, Box<dyn Error>> {
+        let mut rules: Vec<(RelabelConfig, Regex)> = Vec::new();
+        for rule in cfg.iter() {
+            // like Prometheus, regular expressions are anchored at both ends
+            let re = match Regex::new(&format!("^(?:{})$", rule.regex)) {
+                Ok(v) => v,
+                Err(e) => bail!("invalid regex '{}' - {}", rule.regex, e),
+            };
+            match rule.action {
+                Action::Replace | Action::Lowercase | Action::Uppercase => {
+                    if rule.target_label.is_empty() {
+                        bail!("no target_label set for action {:?}", rule.action);
+                    }
+                }
+                Action::Keep | Action::Drop => {
+                    if rule.source_labels.is_empty() {
+                        bail!("no source_labels set for action {:?}", rule.action);
+                    }
+                }
+                Action::Hashmod => {
+                    if rule.target_label.is_empty() {
+                        bail!("no target_label set for action {:?}", rule.action);
+                    }
+                    if rule.modulus == 0 {
+                        bail!(
+                            "modulus must be greater than 0 for action {:?}",
+                            rule.action
+                        );
+                    }
+                }
+                Action::Labelmap | Action::Labeldrop | Action::Labelkeep => {}
+            };
+            rules.push((rule.clone(), re));
+        }
+        Ok(Relabeler { rules })
+    }
+
+    // Apply all rules, returns None if the label set has been dropped
+    pub fn apply(&self, mut labels: HashMap<String, String>) -> Option<HashMap<String, String>> {
+        for (rule, re) in self.rules.iter() {
+            let value = rule
+                .source_labels
+                .iter()
+                .map(|l| labels.get(l).map(|v| v.as_str()).unwrap_or(""))
+                .collect::<Vec<&str>>()
+                .join(&rule.separator);
+
+            match rule.action {
+                Action::Replace => {
+                    if let Some(caps) = re.captures(&value) {
+                        let mut target = String::new();
+                        caps.expand(&rule.target_label, &mut target);
+                        let mut result = String::new();
+                        caps.expand(&rule.replacement, &mut result);
+                        if result.is_empty() {
+                            labels.remove(&target);
+                        } else if !target.is_empty() {
+                            labels.insert(target, result);
+                        }
+                    }
+                }
+                Action::Keep => {
+                    if !re.is_match(&value) {
+                        return None;
+                    }
+                }
+                Action::Drop => {
+                    if re.is_match(&value) {
+                        return None;
+                    }
+                }
+                Action::Labelmap => {
+                    let mut mapped: Vec<(String, String)> = Vec::new();
+                    for (name, v) in labels.iter() {
+                        if let Some(caps) = re.captures(name) {
+                            let mut new_name = String::new();
+                            caps.expand(&rule.replacement, &mut new_name);
+                            mapped.push((new_name, v.clone()));
+                        }
+                    }
+                    labels.extend(mapped);
+                }
+                Action::Labeldrop => labels.retain(|name, _| !re.is_match(name)),
+                Action::Labelkeep => labels.retain(|name, _| re.is_match(name)),
+                Action::Lowercase => {
+                    labels.insert(rule.target_label.clone(), value.to_lowercase());
+                }
+                Action::Uppercase => {
+                    labels.insert(rule.target_label.clone(), value.to_uppercase());
+                }
+                Action::Hashmod => {
+                    labels.insert(
+                        rule.target_label.clone(),
+                        (hash_value(&value) % rule.modulus).to_string(),
+                    );
+                }
+            };
+        }
+        Some(labels)
+    }
+}
+
+// Like Prometheus, the lower 64 bits of the MD5 sum as big endian integer
+fn hash_value(value: &str) -> u64 {
+    match hash(MessageDigest::md5(), value.as_bytes()) {
+        Ok(v) => {
+            let mut lower = [0u8; 8];
+            lower.copy_from_slice(&v[8..16]);
+            u64::from_be_bytes(lower)
+        }
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Labels<'a> = Vec<(&'a str, &'a str)>;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn relabeler(yaml: &str) -> Relabeler {
+        let cfg: Vec<RelabelConfig> = serde_yaml::from_str(yaml).unwrap();
+        Relabeler::new(&cfg).unwrap()
+    }
+
+    #[test]
+    fn actions() {
+        let input = labels(&[
+            ("__address__", "host1:9100"),
+            ("__meta_env", "prod"),
+            ("job", "node"),
+        ]);
+        // (rules, expected labels or None if dropped)
+        let cases: Vec<(&str, Option<Labels>)> = vec![
+            (
+                "- source_labels: ['__address__']\n  regex: '(.*):.*'\n  target_label: host\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                    ("host", "host1"),
+                ]),
+            ),
+            (
+                "- source_labels: ['job', '__meta_env']\n  target_label: id\n  replacement: '${1}!'\n  separator: '/'\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                    ("id", "node/prod!"),
+                ]),
+            ),
+            // no match leaves the labels unchanged
+            (
+                "- source_labels: ['job']\n  regex: 'other'\n  target_label: job\n  replacement: 'x'\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                ]),
+            ),
+            // an empty replacement removes the target label
+            (
+                "- target_label: job\n  replacement: ''\n",
+                Some(vec![("__address__", "host1:9100"), ("__meta_env", "prod")]),
+            ),
+            (
+                "- source_labels: ['__meta_env']\n  regex: 'prod'\n  action: keep\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                ]),
+            ),
+            // regular expressions are anchored
+            (
+                "- source_labels: ['__meta_env']\n  regex: 'pro'\n  action: keep\n",
+                None,
+            ),
+            (
+                "- source_labels: ['__meta_env']\n  regex: 'prod'\n  action: drop\n",
+                None,
+            ),
+            (
+                "- source_labels: ['__meta_env']\n  regex: 'test'\n  action: drop\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                ]),
+            ),
+            (
+                "- regex: '__meta_(.*)'\n  action: labelmap\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                    ("env", "prod"),
+                ]),
+            ),
+            (
+                "- regex: '__meta_.*'\n  action: labeldrop\n",
+                Some(vec![("__address__", "host1:9100"), ("job", "node")]),
+            ),
+            (
+                "- regex: '__.*'\n  action: labelkeep\n",
+                Some(vec![("__address__", "host1:9100"), ("__meta_env", "prod")]),
+            ),
+            (
+                "- source_labels: ['job']\n  target_label: job\n  action: uppercase\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "NODE"),
+                ]),
+            ),
+            (
+                "- source_labels: ['__address__']\n  target_label: __tmp_hash\n  modulus: 8\n  action: hashmod\n- source_labels: ['__tmp_hash']\n  regex: '0'\n  action: keep\n",
+                Some(vec![
+                    ("__address__", "host1:9100"),
+                    ("__meta_env", "prod"),
+                    ("job", "node"),
+                    ("__tmp_hash", "0"),
+                ]),
+            ),
+        ];
+
+        for (rules, expected) in cases {
+            let result = relabeler(rules).apply(input.clone());
+            assert_eq!(result, expected.map(|v| labels(&v)), "rules:\n{}", rules);
+        }
+    }
+
+    #[test]
+    fn hashmod_matches_prometheus() {
+        // md5("host1:9100") = bb0ecb217acca0c2e0ec7e7a45989d50
+        assert_eq!(hash_value("host1:9100"), 0xe0ec7e7a45989d50);
+        assert_eq!(hash_value("host1:9100") % 8, 0);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for rules in [
+            "- regex: '('\n",
+            "- source_labels: ['a']\n  target_label: ''\n",
+            "- regex: 'x'\n  action: keep\n",
+            "- regex: 'x'\n  action: drop\n",
+            "- source_labels: ['a']\n  target_label: b\n  action: hashmod\n",
+            "- source_labels: ['a']\n  modulus: 2\n  action: hashmod\n",
+        ] {
+            let cfg: Vec<RelabelConfig> = serde_yaml::from_str(rules).unwrap();
+            assert!(Relabeler::new(&cfg).is_err(), "rules:\n{}", rules);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config;
+
+use log::debug;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Time of the first scrape of a job. Without alignment and jitter the job is scraped right away.
+// With alignment the first scrape happens at the next multiple of the interval since the epoch,
+// jitter delays the scrape by an offset derived from the host name and the job name
+pub fn first(name: &str, interval: Duration, global: &config::Global) -> Instant {
+    let now = Instant::now();
+    let wall_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let host = gethostname::gethostname().to_string_lossy().to_string();
+
+    let offset = first_offset(name, interval, global, wall_ms, &host);
+    debug!(
+        "'{}': first scrape in {} ms, interval is {} ms",
+        name,
+        offset.as_millis(),
+        interval.as_millis()
+    );
+    now + offset
+}
+
+// Delay of the first scrape for the wall clock time wall_ms (milliseconds since the epoch)
+fn first_offset(
+    name: &str,
+    interval: Duration,
+    global: &config::Global,
+    wall_ms: u128,
+    host: &str,
+) -> Duration {
+    let interval_ms = interval.as_millis().max(1);
+    let mut offset_ms: u128 = 0;
+
+    if global.align {
+        offset_ms = (interval_ms - wall_ms % interval_ms) % interval_ms;
+    }
+    if global.jitter {
+        offset_ms = (offset_ms + jitter(host, name) % interval_ms) % interval_ms;
+    }
+    Duration::from_millis(offset_ms as u64)
+}
+
+// Time of the next scrape, intervals missed (e.g. because the process has been suspended) are skipped
+pub fn next(name: &str, previous: Instant, interval: Duration, now: Instant) -> Instant {
+    let mut next = previous + interval;
+    if next <= now {
+        let missed = (now - previous).as_millis() / interval.as_millis().max(1);
+        debug!("'{}': skipping {} missed scrape intervals", name, missed);
+        next = previous + interval * missed as u32;
+        if next <= now {
+            next += interval;
+        }
+    }
+    next
+}
+
+// Offset in milliseconds, stable across restarts (FNV-1a of host name and job name) so every host keeps its slot
+fn jitter(host: &str, name: &str) -> u128 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in host
+        .bytes()
+        .chain(std::iter::once(b'/'))
+        .chain(name.bytes())
+    {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global(align: bool, jitter: bool) -> config::Global {
+        serde_yaml::from_str(&format!("align: {}\njitter: {}\n", align, jitter)).unwrap()
+    }
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn first_scrape_right_away() {
+        let g = global(false, false);
+        assert_eq!(
+            first_offset("node", MINUTE, &g, 1_700_000_012_345, "host1"),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn first_scrape_aligned() {
+        let g = global(true, false);
+        // (wall clock ms, expected offset ms)
+        for (wall, expected) in [
+            (1_700_000_040_000u128, 0u64),
+            (1_700_000_040_001, 59_999),
+            (1_700_000_065_000, 35_000),
+            (1_700_000_099_999, 1),
+        ] {
+            assert_eq!(
+                first_offset("node", MINUTE, &g, wall, "host1"),
+                Duration::from_millis(expected),
+                "{}",
+                wall
+            );
+        }
+        assert_eq!(
+            first_offset(
+                "node",
+                Duration::from_secs(15),
+                &g,
+                1_700_000_065_000,
+                "host1"
+            ),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn jitter_is_stable_and_within_the_interval() {
+        let g = global(false, true);
+        for host in ["host1", "host2", "a-very-long-host-name.example.com"] {
+            for name in ["node", "blackbox/https://www.example.com", ""] {
+                for interval in [1, 15, 60, 3600] {
+                    let interval = Duration::from_secs(interval);
+                    let offset = first_offset(name, interval, &g, 1_700_000_012_345, host);
+                    assert!(offset < interval, "{} {} {:?}", host, name, interval);
+                    // doesn't depend on the time of the start
+                    assert_eq!(
+                        offset,
+                        first_offset(name, interval, &g, 1_700_000_099_999, host)
+                    );
+                }
+            }
+        }
+        assert_ne!(
+            first_offset("node", MINUTE, &g, 0, "host1"),
+            first_offset("node", MINUTE, &g, 0, "host2")
+        );
+    }
+
+    #[test]
+    fn jitter_is_added_to_the_alignment() {
+        let g = global(true, true);
+        let jitter_ms = (jitter("host1", "node") % 60_000) as u64;
+        assert_eq!(
+            first_offset("node", MINUTE, &g, 1_700_000_040_000, "host1"),
+            Duration::from_millis(jitter_ms)
+        );
+        assert_eq!(
+            first_offset("node", MINUTE, &g, 1_700_000_070_000, "host1"),
+            Duration::from_millis((30_000 + jitter_ms) % 60_000)
+        );
+    }
+
+    #[test]
+    fn next_scrape() {
+        let start = Instant::now();
+        // (now relative to the previous scrape, expected next scrape relative to the previous scrape)
+        for (now, expected) in [
+            (Duration::ZERO, MINUTE),
+            (Duration::from_secs(59), MINUTE),
+            // the scrape took longer than the interval, missed intervals are skipped
+            (MINUTE, MINUTE * 2),
+            (Duration::from_secs(61), MINUTE * 2),
+            (Duration::from_secs(210), MINUTE * 4),
+            (MINUTE * 4, MINUTE * 5),
+        ] {
+            assert_eq!(
+                next("node", start, MINUTE, start + now),
+                start + expected,
+                "{:?}",
+                now
+            );
+        }
+    }
+}
@@ -1,114 +1,1033 @@
 use crate::config;
+use crate::constants;
+use crate::data;
+use crate::discovery;
 use crate::exporter;
 use crate::http;
+use crate::local;
 use crate::massage;
+use crate::relabel;
+use crate::schedule;
 
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use std::collections::HashSet;
 use std::error::Error;
-use std::sync::mpsc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc, Mutex};
 use std::{thread, time};
 
-pub fn run(
-    cfg: &mut config::Configuration,
-    sender: mpsc::Sender<Vec<u8>>,
-) -> Result<(), Box<dyn Error>> {
-    let one_second = time::Duration::from_secs(1);
-    let mut now: i64;
+// Where the data of a scrape comes from
+enum Source {
+    Http {
+        client: http::Client,
+        request_url: String,
+    },
+    Textfile(String),
+    Exec(config::Exec, time::Duration),
+}
 
-    loop {
-        now = chrono::Local::now().timestamp();
-        let mut data: Vec<global::payload::Message> = Vec::new();
+// A single scrape, processed by one of the worker threads
+struct Job {
+    interval: i64,
+    labels: std::collections::HashMap<String, String>,
+    metric_relabeler: Option<Arc<relabel::Relabeler>>,
+    name: String,
+    retry: config::Retry,
+    // retries must be finished before the next scrape of the job is due
+    retry_until: time::Instant,
+    source: Source,
+    url: String,
+}
 
-        // Iterate of scrape list
-        for scrape in cfg.scrape.iter_mut() {
-            // Even the scrape interval has not passed, create a HTTP client if it does not exist
-            let timeout = match scrape.timeout {
-                Some(v) => v,
-                None => cfg.global.timeout,
-            };
-            if scrape.http_client.is_none() {
-                scrape.http_client = Some(http::build_http_client(timeout)?);
-            }
+struct Outcome {
+    data: global::payload::Message,
+    duration: f64,
+    http_errors: Vec<u16>,
+    name: String,
+    retries: u32,
+    success: bool,
+}
+
+// Scrapes are processed by a fixed number of worker threads, so a slow target only blocks its own worker
+struct Pool {
+    // scrapes that can't be started are reported as failed without a worker
+    failures: mpsc::Sender<Outcome>,
+    jobs: mpsc::Sender<Job>,
+    results: mpsc::Receiver<Outcome>,
+    running: HashSet<String>,
+}
 
-            // check if the interval has been reached
+impl Pool {
+    fn new(size: usize) -> Self {
+        let (job_send, job_recv) = mpsc::channel::<Job>();
+        let (result_send, result_recv) = mpsc::channel::<Outcome>();
+        let job_recv = Arc::new(Mutex::new(job_recv));
+
+        debug!("spawning {} scrape worker threads", size);
+        for _ in 0..size {
+            let jobs = job_recv.clone();
+            let results = result_send.clone();
+            thread::spawn(move || worker(jobs, results));
+        }
+
+        Pool {
+            failures: result_send,
+            jobs: job_send,
+            results: result_recv,
+            running: HashSet::new(),
+        }
+    }
+
+    // Queue all jobs which are due (or all jobs if all is set) and which are not still running
+    fn dispatch(
+        &mut self,
+        targets: &mut [config::Scrape],
+        global: &config::Global,
+        now: time::Instant,
+        all: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        for scrape in targets.iter_mut() {
+            // Even the scrape interval has not passed, create a HTTP client if it does not exist.
+            // Errors are reported when the job is due
+            let prepared = prepare(scrape, global.timeout);
+
+            // check if the job is due
             let interval = match scrape.interval {
                 Some(v) => v,
-                None => cfg.global.interval,
+                None => global.interval,
             };
+            let period = time::Duration::from_secs(interval as u64);
+            let due = *scrape
+                .next_scrape
+                .get_or_insert_with(|| schedule::first(&scrape.name, period, global));
 
-            if (now - scrape.last_scrape) >= interval {
-                let scrp = std::time::Instant::now();
+            if !all && now < due {
+                continue;
+            }
 
-                debug!(
-                    "{} - {} == {}, interval is {} -> start scraping {}",
-                    now,
-                    scrape.last_scrape,
-                    now - scrape.last_scrape,
-                    interval,
+            // the schedule of a job doesn't depend on the duration of its scrapes
+            let next = schedule::next(&scrape.name, due, period, now);
+            scrape.next_scrape = Some(next);
+
+            if self.running.contains(&scrape.name) {
+                warn!(
+                    "'{}': previous scrape is still running, skipping this interval",
                     scrape.name
                 );
-                // scrape data
-                info!("'{}': scraping data from {}", scrape.name, scrape.url);
-
-                let cli = match &scrape.http_client {
-                    Some(v) => v,
-                    None => {
-                        panic!("Uninitialized HTTP client for scrape {}", scrape.name);
-                    }
-                };
-                let raw = match http::get(cli, &scrape.url) {
+            } else {
+                let source = match prepared.and_then(|_| source(scrape, global.timeout)) {
                     Ok(v) => v,
                     Err(e) => {
-                        error!("scraping of {} failed: {}", scrape.url, e);
-                        exporter::SCRAPE_SUCCESS
-                            .with_label_values(&[&scrape.name])
-                            .set(0);
-                        scrape.last_scrape = now;
+                        error!(scrape = scrape.name.as_str();
+                            "'{}': can't scrape {} - {}",
+                            scrape.name,
+                            source_name(scrape),
+                            e
+                        );
+                        self.failures.send(Outcome {
+                            data: massage::failed_scrape_message(
+                                &scrape.name,
+                                &scrape.labels,
+                                interval,
+                                0.0,
+                                0,
+                            ),
+                            duration: 0.0,
+                            http_errors: Vec::new(),
+                            name: scrape.name.clone(),
+                            retries: 0,
+                            success: false,
+                        })?;
+                        self.running.insert(scrape.name.clone());
                         continue;
                     }
                 };
+                debug!(
+                    "'{}': start scraping, interval is {} seconds",
+                    scrape.name, interval
+                );
+                self.jobs.send(Job {
+                    interval,
+                    labels: scrape.labels.clone(),
+                    metric_relabeler: scrape.metric_relabeler.clone(),
+                    name: scrape.name.clone(),
+                    retry: scrape.retry.clone(),
+                    retry_until: retry_until(
+                        next,
+                        time::Duration::from_secs(scrape.timeout.unwrap_or(global.timeout)),
+                        now,
+                    ),
+                    source,
+                    url: source_name(scrape),
+                })?;
+                self.running.insert(scrape.name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // Gather the results of finished scrapes, returns the parsed data and the number of failed scrapes.
+    // Waits until all running scrapes are finished or the deadline has been reached, without a deadline
+    // until all running scrapes are finished
+    fn collect(
+        &mut self,
+        targets: &mut [config::Scrape],
+        deadline: Option<time::Instant>,
+    ) -> Result<(Vec<global::payload::Message>, usize), Box<dyn Error>> {
+        let mut data: Vec<global::payload::Message> = Vec::new();
+        let mut failed: usize = 0;
+
+        while !self.running.is_empty() {
+            let outcome = match deadline {
+                Some(d) => {
+                    match self
+                        .results
+                        .recv_timeout(d.saturating_duration_since(time::Instant::now()))
+                    {
+                        Ok(v) => v,
+                        Err(mpsc::RecvTimeoutError::Timeout) => break,
+                        Err(e) => return Err(Box::new(e)),
+                    }
+                }
+                None => self.results.recv()?,
+            };
+            self.running.remove(&outcome.name);
 
-                let scrp_elapsed = scrp.elapsed().as_secs_f64();
+            // the job may have been removed by a configuration reload while it was running
+            let scrape = match targets.iter_mut().find(|s| s.name == outcome.name) {
+                Some(v) => v,
+                None => {
+                    debug!(
+                        "'{}': discarding result of removed scrape job",
+                        outcome.name
+                    );
+                    continue;
+                }
+            };
+
+            for code in outcome.http_errors.iter() {
+                exporter::SCRAPE_HTTP_ERRORS
+                    .with_label_values(&[&outcome.name, &code.to_string()])
+                    .inc();
+            }
+            if outcome.retries > 0 {
+                exporter::SCRAPE_RETRIES
+                    .with_label_values(&[&outcome.name])
+                    .inc_by(outcome.retries as u64);
+            }
+
+            if outcome.success {
                 exporter::SCRAPE_DURATION
-                    .with_label_values(&[&scrape.name])
-                    .observe(scrp_elapsed);
+                    .with_label_values(&[&outcome.name])
+                    .observe(outcome.duration);
                 exporter::SCRAPE_SUCCESS
-                    .with_label_values(&[&scrape.name])
+                    .with_label_values(&[&outcome.name])
                     .set(1);
+                scrape.last_data = Some(outcome.data.clone());
+                scrape.resends = 0;
+                data.push(outcome.data);
+            } else {
+                exporter::SCRAPE_SUCCESS
+                    .with_label_values(&[&outcome.name])
+                    .set(0);
+                failed += 1;
+                if let Some(v) = failure_message(scrape, outcome.data) {
+                    data.push(v);
+                }
+            }
+        }
+
+        Ok((data, failed))
+    }
+}
 
-                info!(
-                    "'{}': scraped {} bytes from {} for {} in {} seconds",
+// Message to send for a failed scrape according to the on_failure policy of the job, failed contains the
+// synthetic series of the failed scrape
+fn failure_message(
+    scrape: &mut config::Scrape,
+    mut failed: global::payload::Message,
+) -> Option<global::payload::Message> {
+    match scrape.on_failure.policy {
+        config::FailurePolicy::None => {
+            debug!("'{}': not sending data for failed scrape", scrape.name);
+            return None;
+        }
+        config::FailurePolicy::Resend => {
+            if scrape.resends < scrape.on_failure.max_resends {
+                if let Some(v) = &scrape.last_data {
+                    scrape.resends += 1;
+                    info!(
+                        "'{}': resending data of the last successful scrape ({} of {})",
+                        scrape.name, scrape.resends, scrape.on_failure.max_resends
+                    );
+                    let mut msg = v.clone();
+                    massage::replace_scrape_metrics(&mut msg, failed);
+                    return Some(msg);
+                }
+            }
+        }
+        config::FailurePolicy::Marker => {}
+    };
+
+    debug!("'{}': sending failure marker", scrape.name);
+    failed.failed = true;
+    Some(failed)
+}
+
+fn worker(jobs: Arc<Mutex<mpsc::Receiver<Job>>>, results: mpsc::Sender<Outcome>) {
+    loop {
+        // the lock is only held while waiting for the next job
+        let job = match jobs.lock() {
+            Ok(v) => match v.recv() {
+                Ok(j) => j,
+                Err(_) => return,
+            },
+            Err(_) => return,
+        };
+
+        if results.send(scrape(&job)).is_err() {
+            return;
+        }
+    }
+}
+
+fn is_http(scrape: &config::Scrape) -> bool {
+    scrape.textfile.is_none() && scrape.exec.is_none()
+}
+
+// Create the HTTP client and the metric relabeler of a scrape job if they don't exist yet. Clients
+// using tls_config.server_name are recreated from time to time to resolve the address of the host again
+fn prepare(scrape: &mut config::Scrape, default_timeout: u64) -> Result<(), Box<dyn Error>> {
+    if let Some(resolved) = scrape.http_client_resolved {
+        if resolved.elapsed() >= time::Duration::from_secs(constants::SERVER_NAME_RESOLVE_INTERVAL)
+        {
+            scrape.http_client_resolved = Some(time::Instant::now());
+            match http::build_client(scrape, default_timeout) {
+                Ok(v) => scrape.http_client = Some(v),
+                Err(e) => warn!(scrape = scrape.name.as_str();
+                    "'{}': can't resolve {} again, using the previous address - {}",
                     scrape.name,
-                    raw.len(),
                     scrape.url,
-                    scrape.name,
-                    scrp_elapsed,
-                );
+                    e
+                ),
+            };
+        }
+    }
+    if scrape.http_client.is_none() && is_http(scrape) {
+        http::request_url(scrape)?;
+        scrape.http_client = Some(http::build_client(scrape, default_timeout)?);
+        if scrape
+            .tls_config
+            .as_ref()
+            .is_some_and(|t| !t.server_name.is_empty())
+        {
+            scrape.http_client_resolved = Some(time::Instant::now());
+        }
+    }
+    if scrape.metric_relabeler.is_none() && !scrape.metric_relabel_configs.is_empty() {
+        scrape.metric_relabeler = Some(Arc::new(relabel::Relabeler::new(
+            &scrape.metric_relabel_configs,
+        )?));
+    }
+    Ok(())
+}
+
+fn source(scrape: &config::Scrape, default_timeout: u64) -> Result<Source, Box<dyn Error>> {
+    if let Some(v) = &scrape.textfile {
+        return Ok(Source::Textfile(v.directory.clone()));
+    }
+    if let Some(v) = &scrape.exec {
+        return Ok(Source::Exec(
+            v.clone(),
+            time::Duration::from_secs(scrape.timeout.unwrap_or(default_timeout)),
+        ));
+    }
 
-                // Massage raw Prometheus data into MQTT payload
-                let parsed =
-                    massage::parse_scrape_data(&raw, &scrape.name, &scrape.labels, interval)?;
-                data.push(parsed);
+    let client = match &scrape.http_client {
+        Some(v) => v.clone(),
+        None => http::build_client(scrape, default_timeout)?,
+    };
+    Ok(Source::Http {
+        client,
+        request_url: http::request_url(scrape)?,
+    })
+}
 
-                debug!("updating scrape.last_scrape stamp to {}", now);
-                scrape.last_scrape = now;
-            } else {
-                debug!(
-                    "{} - {} == {}, interval is {} -> scraping of {} not yet required",
-                    now,
-                    scrape.last_scrape,
-                    now - scrape.last_scrape,
-                    interval,
-                    scrape.name
-                );
+// Source of a scrape as shown in log messages
+pub fn source_name(scrape: &config::Scrape) -> String {
+    if let Some(v) = &scrape.textfile {
+        return format!("textfile directory {}", v.directory);
+    }
+    if let Some(v) = &scrape.exec {
+        return format!("command {}", v.command);
+    }
+    http::display_url(scrape)
+}
+
+fn fetch(source: &Source) -> Result<String, Box<dyn Error>> {
+    match source {
+        Source::Http {
+            client,
+            request_url,
+        } => http::get(client, request_url),
+        Source::Textfile(directory) => local::textfile(directory),
+        Source::Exec(cfg, timeout) => local::exec(cfg, *timeout),
+    }
+}
+
+// Fetch the data of a scrape job once, used by --check --connect
+pub fn check(scrape: &config::Scrape, default_timeout: u64) -> Result<(), Box<dyn Error>> {
+    fetch(&source(scrape, default_timeout)?)?;
+    Ok(())
+}
+
+// Result of fetching the data of a job, including all retries
+struct Attempts {
+    http_errors: Vec<u16>,
+    result: Result<String, Box<dyn Error>>,
+    retries: u32,
+    // start of the last attempt
+    start: time::Instant,
+}
+
+// Fetch the data, failed attempts are retried according to the retry settings of the job as long as
+// the backoff ends before retry_until
+fn fetch_with_retries(job: &Job) -> Attempts {
+    let mut http_errors: Vec<u16> = Vec::new();
+    let mut retries: u32 = 0;
+    loop {
+        let start = time::Instant::now();
+        let result = fetch(&job.source);
+        let e = match &result {
+            Ok(_) => {
+                return Attempts {
+                    http_errors,
+                    result,
+                    retries,
+                    start,
+                }
+            }
+            Err(e) => e,
+        };
+        let failure = http::classify(e.as_ref());
+        if let http::Failure::Status(v) = failure {
+            http_errors.push(v);
+        }
+
+        if retries >= job.retry.attempts || !retryable(&job.retry, &failure) {
+            return Attempts {
+                http_errors,
+                result,
+                retries,
+                start,
+            };
+        }
+        let backoff = backoff(&job.retry, retries);
+        if time::Instant::now() + backoff > job.retry_until {
+            debug!(
+                "'{}': no time left for another retry before the next scrape",
+                job.name
+            );
+            return Attempts {
+                http_errors,
+                result,
+                retries,
+                start,
+            };
+        }
+
+        retries += 1;
+        warn!(scrape = job.name.as_str(), url = job.url.as_str();
+            "'{}': scraping of {} failed, retrying in {} seconds ({} of {}): {}",
+            job.name,
+            job.url,
+            backoff.as_secs_f64(),
+            retries,
+            job.retry.attempts,
+            e
+        );
+        thread::sleep(backoff);
+    }
+}
+
+// Retries must start early enough to finish (including the timeout) before the next scrape is due
+fn retry_until(next: time::Instant, timeout: time::Duration, now: time::Instant) -> time::Instant {
+    next.checked_sub(timeout).unwrap_or(now).max(now)
+}
+
+// Wait before the next retry, doubled with every retry up to max_backoff
+fn backoff(retry: &config::Retry, retries: u32) -> time::Duration {
+    let seconds = retry.initial_backoff * 2_f64.powi(retries.min(i32::MAX as u32) as i32);
+    time::Duration::from_secs_f64(seconds.min(retry.max_backoff))
+}
+
+fn retryable(retry: &config::Retry, failure: &http::Failure) -> bool {
+    match failure {
+        http::Failure::Error(v) => retry.errors.contains(v),
+        http::Failure::Status(v) => retry.status_codes.contains(v),
+        http::Failure::Other => false,
+    }
+}
+
+// Returns the parsed data including the synthetic scrape series, the success of the scrape, the
+// duration of fetching the data (of the last attempt), the number of retries and the HTTP status
+// codes of failed attempts
+fn scrape(job: &Job) -> Outcome {
+    info!(scrape = job.name.as_str(), url = job.url.as_str(); "'{}': scraping data from {}", job.name, job.url);
+    let attempts = fetch_with_retries(job);
+    let retries = attempts.retries;
+    let failed = |duration: f64| Outcome {
+        data: massage::failed_scrape_message(
+            &job.name,
+            &job.labels,
+            job.interval,
+            duration,
+            retries,
+        ),
+        duration,
+        http_errors: attempts.http_errors.clone(),
+        name: job.name.clone(),
+        retries,
+        success: false,
+    };
+
+    let raw = match &attempts.result {
+        Ok(v) => v,
+        Err(e) => {
+            error!(scrape = job.name.as_str(), url = job.url.as_str();
+                "scraping of {} failed: {}", job.url, e
+            );
+            return failed(attempts.start.elapsed().as_secs_f64());
+        }
+    };
+
+    let scrp_elapsed = attempts.start.elapsed().as_secs_f64();
+    info!(scrape = job.name.as_str(), url = job.url.as_str(), bytes = raw.len(), duration = scrp_elapsed;
+        "'{}': scraped {} bytes from {} in {} seconds",
+        job.name,
+        raw.len(),
+        job.url,
+        scrp_elapsed,
+    );
+
+    // Massage raw Prometheus data into MQTT payload
+    match massage::parse_scrape_data(
+        raw,
+        &job.name,
+        &job.labels,
+        job.interval,
+        job.metric_relabeler.as_deref(),
+    ) {
+        Ok(mut v) => {
+            massage::add_scrape_metrics(
+                &mut v,
+                &job.labels,
+                true,
+                scrp_elapsed,
+                raw.len(),
+                retries,
+            );
+            Outcome {
+                data: v,
+                duration: scrp_elapsed,
+                http_errors: attempts.http_errors.clone(),
+                name: job.name.clone(),
+                retries,
+                success: true,
             }
         }
+        Err(e) => {
+            error!(
+                scrape = job.name.as_str(), url = job.url.as_str();
+                "can't parse data scraped from {}: {}", job.url, e
+            );
+            failed(scrp_elapsed)
+        }
+    }
+}
+
+pub fn run(
+    cfg: &mut config::Configuration,
+    sender: mpsc::Sender<data::Data>,
+    reload: mpsc::Receiver<config::Configuration>,
+    stop: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    let one_second = time::Duration::from_secs(1);
+    let mut pool = Pool::new(cfg.global.concurrency);
+    let mut discovery = discovery::Discovery::new();
+    let mut targets = discovery.targets(cfg, &[]);
+
+    loop {
+        if global::shutdown::requested(stop) {
+            info!("stopping scrape loop");
+            return Ok(());
+        }
+
+        if let Ok(v) = reload.try_recv() {
+            let new_targets = discovery.targets(&v, &targets);
+            targets = update_targets(&targets, new_targets, &cfg.global, &v.global);
+            *cfg = v;
+        } else if discovery.changed(cfg) {
+            let new_targets = discovery.targets(cfg, &targets);
+            targets = update_targets(&targets, new_targets, &cfg.global, &cfg.global);
+        }
+
+        let now = time::Instant::now();
+        pool.dispatch(&mut targets, &cfg.global, now, false)?;
+
+        // wake up for the next scrape, but at least once a second to handle reloads and shutdown requests
+        let wakeup = targets
+            .iter()
+            .filter_map(|s| s.next_scrape)
+            .min()
+            .unwrap_or(now + one_second)
+            .min(now + one_second);
+        // publish the results of fast scrapes right away, slow scrapes are published later
+        let (data, _) = pool.collect(&mut targets, Some(wakeup))?;
+
         if !data.is_empty() {
             // send to MQTT thread
             debug!("sending data to MQTT thread");
             let mqtt_msg = massage::build_mqtt_message(&data, cfg.global.compress)?;
-            sender.send(mqtt_msg)?;
+            sender.send(data::Data::Payload(mqtt_msg))?;
+        };
+        thread::sleep(wakeup.saturating_duration_since(time::Instant::now()));
+    }
+}
+
+// Scrape every job once and wait for all results, returns the parsed data and the number of failed scrapes
+fn scrape_all(
+    cfg: &mut config::Configuration,
+) -> Result<(Vec<global::payload::Message>, usize), Box<dyn Error>> {
+    let mut pool = Pool::new(cfg.global.concurrency);
+    let mut targets = discovery::Discovery::new().targets(cfg, &[]);
+    pool.dispatch(&mut targets, &cfg.global, time::Instant::now(), true)?;
+    pool.collect(&mut targets, None)
+}
+
+// Scrape every job once and send the data to the MQTT thread, returns the number of failed scrapes
+pub fn once(
+    cfg: &mut config::Configuration,
+    sender: mpsc::Sender<data::Data>,
+) -> Result<usize, Box<dyn Error>> {
+    let (data, failed) = scrape_all(cfg)?;
+    if !data.is_empty() {
+        debug!("sending data to MQTT thread");
+        let mqtt_msg = massage::build_mqtt_message(&data, cfg.global.compress)?;
+        sender.send(data::Data::Payload(mqtt_msg))?;
+    }
+    Ok(failed)
+}
+
+// Scrape every job once and print the payload instead of sending it, returns the number of failed scrapes
+pub fn dry_run(cfg: &mut config::Configuration) -> Result<usize, Box<dyn Error>> {
+    let (data, failed) = scrape_all(cfg)?;
+
+    println!("{}", serde_json::to_string_pretty(&data)?);
+    println!();
+    for msg in data.iter() {
+        println!(
+            "'{}': {} metrics, {} bytes",
+            msg.name,
+            msg.payload.len(),
+            serde_json::to_string(msg)?.len()
+        );
+    }
+    let payload = massage::payload_json(&data)?;
+    if cfg.global.compress {
+        let compressed = massage::compress_data(payload.clone())?;
+        println!(
+            "payload: {} bytes, {} bytes gzip compressed",
+            payload.len(),
+            compressed.len()
+        );
+    } else {
+        println!("payload: {} bytes", payload.len());
+    }
+    Ok(failed)
+}
+
+// Build the new list of scrapes, scrapes that didn't change keep their timing and HTTP client.
+// Changed scrapes that can't be set up keep their previous settings
+fn update_targets(
+    old_targets: &[config::Scrape],
+    mut targets: Vec<config::Scrape>,
+    old_global: &config::Global,
+    global: &config::Global,
+) -> Vec<config::Scrape> {
+    for scrape in targets.iter_mut() {
+        let old = match old_targets.iter().find(|s| s.name == scrape.name) {
+            Some(v) => v,
+            None => {
+                info!("'{}': new scrape job", scrape.name);
+                continue;
+            }
         };
-        thread::sleep(one_second);
+
+        if old.url == scrape.url
+            && old.labels == scrape.labels
+            && old.metric_relabel_configs == scrape.metric_relabel_configs
+            && old.textfile == scrape.textfile
+            && old.exec == scrape.exec
+            && old.retry == scrape.retry
+            && old.interval.unwrap_or(old_global.interval)
+                == scrape.interval.unwrap_or(global.interval)
+            && old.timeout.unwrap_or(old_global.timeout) == scrape.timeout.unwrap_or(global.timeout)
+            && old_global.align == global.align
+            && old_global.jitter == global.jitter
+            && !config::client_settings_changed(old, scrape)
+        {
+            debug!("'{}': scrape job is unchanged", scrape.name);
+            scrape.next_scrape = old.next_scrape;
+            scrape.last_data = old.last_data.clone();
+            scrape.resends = old.resends;
+            scrape.http_client = old.http_client.clone();
+            scrape.http_client_resolved = old.http_client_resolved;
+        } else if let Err(e) = prepare(scrape, global.timeout) {
+            error!(scrape = scrape.name.as_str();
+                "'{}': can't apply the changed scrape job, keeping the previous settings - {}",
+                scrape.name,
+                e
+            );
+            *scrape = old.clone();
+        } else {
+            info!("'{}': scrape job has been changed", scrape.name);
+        }
+    }
+
+    for old in old_targets.iter() {
+        if !targets.iter().any(|s| s.name == old.name) {
+            info!("'{}': scrape job has been removed", old.name);
+            let _ = exporter::SCRAPE_SUCCESS.remove_label_values(&[&old.name]);
+            let _ = exporter::SCRAPE_DURATION.remove_label_values(&[&old.name]);
+            let _ = exporter::SCRAPE_RETRIES.remove_label_values(&[&old.name]);
+            exporter::remove_http_errors(&old.name);
+        }
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> config::Global {
+        serde_yaml::from_str("interval: 60").unwrap()
+    }
+
+    fn scrape(yaml: &str) -> config::Scrape {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn broken_targets_fail_without_stopping_the_others() {
+        let dir = std::env::temp_dir().join(format!(
+            "prometheus-mqtt-transport-test-dispatch-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ok.prom"), "ok_metric 1\n").unwrap();
+
+        let mut targets = vec![
+            scrape(
+                "name: broken\nurl: https://127.0.0.1:1/metrics\ntls_config:\n  ca_file: /nonexistent/ca.crt\n",
+            ),
+            scrape(&format!(
+                "name: working\ntextfile:\n  directory: {}\n",
+                dir.display()
+            )),
+        ];
+        let mut pool = Pool::new(1);
+        pool.dispatch(&mut targets, &global(), time::Instant::now(), true)
+            .unwrap();
+        let (data, failed) = pool.collect(&mut targets, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(failed, 1);
+        assert_eq!(data.len(), 2);
+        let broken = data.iter().find(|m| m.name == "broken").unwrap();
+        assert!(broken.failed);
+        let working = data.iter().find(|m| m.name == "working").unwrap();
+        assert!(!working.failed);
+        // the next dispatch tries to create the client again
+        assert!(targets[0].http_client.is_none());
+        assert!(targets[0].next_scrape.is_some());
+    }
+
+    #[test]
+    fn server_name_is_resolved_again() {
+        let mut s = scrape(
+            "name: pinned\nurl: https://127.0.0.1:9100/metrics\ntls_config:\n  server_name: exporter.internal\n",
+        );
+        prepare(&mut s, 10).unwrap();
+        let resolved = s.http_client_resolved.unwrap();
+
+        prepare(&mut s, 10).unwrap();
+        assert_eq!(s.http_client_resolved, Some(resolved));
+
+        let expired = time::Instant::now()
+            .checked_sub(time::Duration::from_secs(
+                constants::SERVER_NAME_RESOLVE_INTERVAL,
+            ))
+            .unwrap();
+        s.http_client_resolved = Some(expired);
+        prepare(&mut s, 10).unwrap();
+        assert!(s.http_client_resolved.unwrap() > expired);
+
+        let mut s = scrape("name: plain\nurl: https://127.0.0.1:9100/metrics\n");
+        prepare(&mut s, 10).unwrap();
+        assert!(s.http_client.is_some());
+        assert!(s.http_client_resolved.is_none());
+    }
+
+    #[test]
+    fn broken_changes_keep_the_previous_target() {
+        let global = global();
+        let mut old = vec![
+            scrape(
+                "name: a
+url: http://127.0.0.1:1/metrics
+",
+            ),
+            scrape(
+                "name: b
+url: http://127.0.0.1:2/metrics
+",
+            ),
+        ];
+        for s in old.iter_mut() {
+            prepare(s, global.timeout).unwrap();
+            s.next_scrape = Some(time::Instant::now());
+        }
+
+        let new = vec![
+            scrape(
+                "name: a
+url: https://127.0.0.1:1/metrics
+tls_config:
+  ca_file: /nonexistent/ca.crt
+",
+            ),
+            scrape(
+                "name: b
+url: http://127.0.0.1:3/metrics
+",
+            ),
+            scrape(
+                "name: c
+url: https://127.0.0.1:4/metrics
+tls_config:
+  ca_file: /nonexistent/ca.crt
+",
+            ),
+        ];
+        let targets = update_targets(&old, new, &global, &global);
+
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[0].url, "http://127.0.0.1:1/metrics");
+        assert_eq!(targets[0].next_scrape, old[0].next_scrape);
+        assert!(targets[0].http_client.is_some());
+        assert_eq!(targets[1].url, "http://127.0.0.1:3/metrics");
+        assert!(targets[1].next_scrape.is_none());
+        // new jobs are added and fail when they are due
+        assert_eq!(targets[2].name, "c");
+        assert!(targets[2].http_client.is_none());
+    }
+
+    fn retry(yaml: &str) -> config::Retry {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    // Serves one connection per status code, 2xx replies contain a single sample
+    fn http_server(statuses: Vec<u16>) -> (String, std::thread::JoinHandle<()>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request: Vec<u8> = Vec::new();
+                let mut buffer = [0_u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buffer).unwrap() {
+                        0 => break,
+                        n => request.extend_from_slice(&buffer[..n]),
+                    };
+                }
+                let body = if (200..300).contains(&status) {
+                    "up 1\n"
+                } else {
+                    ""
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (url, handle)
+    }
+
+    fn job(url: &str, retry: &str, retry_until: time::Instant) -> Job {
+        let mut s = scrape(&format!("name: retried\nurl: {}\nretry:\n{}", url, retry));
+        prepare(&mut s, 5).unwrap();
+        Job {
+            interval: 60,
+            labels: std::collections::HashMap::new(),
+            metric_relabeler: None,
+            name: s.name.clone(),
+            retry: s.retry.clone(),
+            retry_until,
+            source: source(&s, 5).unwrap(),
+            url: s.url.clone(),
+        }
+    }
+
+    #[test]
+    fn retryable_failures() {
+        let defaults = retry("attempts: 2");
+        for failure in [
+            http::Failure::Error(config::RetryError::Connect),
+            http::Failure::Error(config::RetryError::Reset),
+            http::Failure::Error(config::RetryError::Timeout),
+            http::Failure::Status(429),
+            http::Failure::Status(502),
+            http::Failure::Status(503),
+            http::Failure::Status(504),
+        ] {
+            assert!(retryable(&defaults, &failure), "{:?}", failure);
+        }
+        for failure in [
+            http::Failure::Status(400),
+            http::Failure::Status(404),
+            http::Failure::Status(500),
+            http::Failure::Other,
+        ] {
+            assert!(!retryable(&defaults, &failure), "{:?}", failure);
+        }
+
+        let custom = retry("attempts: 2\nerrors: [timeout]\nstatus_codes: [500]");
+        assert!(retryable(
+            &custom,
+            &http::Failure::Error(config::RetryError::Timeout)
+        ));
+        assert!(!retryable(
+            &custom,
+            &http::Failure::Error(config::RetryError::Connect)
+        ));
+        assert!(retryable(&custom, &http::Failure::Status(500)));
+        assert!(!retryable(&custom, &http::Failure::Status(503)));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_backoff() {
+        let r = retry("attempts: 10\ninitial_backoff: 1\nmax_backoff: 30");
+        let expected = [1, 2, 4, 8, 16, 30, 30];
+        for (retries, seconds) in expected.iter().enumerate() {
+            assert_eq!(
+                backoff(&r, retries as u32),
+                time::Duration::from_secs(*seconds),
+                "retries {}",
+                retries
+            );
+        }
+        assert_eq!(backoff(&r, u32::MAX), time::Duration::from_secs(30));
+
+        let r = retry("attempts: 10\ninitial_backoff: 0.25\nmax_backoff: 1.5");
+        assert_eq!(backoff(&r, 0), time::Duration::from_millis(250));
+        assert_eq!(backoff(&r, 2), time::Duration::from_secs(1));
+        assert_eq!(backoff(&r, 3), time::Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn retries_finish_before_the_next_scrape() {
+        let now = time::Instant::now();
+        let next = now + time::Duration::from_secs(60);
+        assert_eq!(
+            retry_until(next, time::Duration::from_secs(10), now),
+            now + time::Duration::from_secs(50)
+        );
+        // the timeout leaves no time for retries
+        assert_eq!(retry_until(next, time::Duration::from_secs(60), now), now);
+        assert_eq!(retry_until(next, time::Duration::from_secs(120), now), now);
+    }
+
+    #[test]
+    fn retried_status_codes() {
+        let (url, server) = http_server(vec![503, 502, 200]);
+        let j = job(
+            &url,
+            "  attempts: 3\n  initial_backoff: 0.01\n  max_backoff: 0.02\n",
+            time::Instant::now() + time::Duration::from_secs(60),
+        );
+        let attempts = fetch_with_retries(&j);
+        server.join().unwrap();
+        assert_eq!(attempts.result.unwrap(), "up 1\n");
+        assert_eq!(attempts.retries, 2);
+        assert_eq!(attempts.http_errors, vec![503, 502]);
+    }
+
+    #[test]
+    fn attempts_limit_retries() {
+        let (url, server) = http_server(vec![503, 503]);
+        let j = job(
+            &url,
+            "  attempts: 1\n  initial_backoff: 0.01\n  max_backoff: 0.02\n",
+            time::Instant::now() + time::Duration::from_secs(60),
+        );
+        let attempts = fetch_with_retries(&j);
+        server.join().unwrap();
+        assert!(attempts.result.is_err());
+        assert_eq!(attempts.retries, 1);
+        assert_eq!(attempts.http_errors, vec![503, 503]);
+    }
+
+    #[test]
+    fn other_status_codes_are_not_retried() {
+        let (url, server) = http_server(vec![404]);
+        let j = job(
+            &url,
+            "  attempts: 3\n  initial_backoff: 0.01\n  max_backoff: 0.02\n",
+            time::Instant::now() + time::Duration::from_secs(60),
+        );
+        let attempts = fetch_with_retries(&j);
+        server.join().unwrap();
+        assert!(attempts.result.is_err());
+        assert_eq!(attempts.retries, 0);
+        assert_eq!(attempts.http_errors, vec![404]);
+    }
+
+    #[test]
+    fn retries_stop_when_the_budget_is_used_up() {
+        // the backoff of the second retry doesn't fit into the remaining time
+        let (url, server) = http_server(vec![503, 503]);
+        let j = job(
+            &url,
+            "  attempts: 5\n  initial_backoff: 0.2\n  max_backoff: 10\n",
+            time::Instant::now() + time::Duration::from_millis(500),
+        );
+        let attempts = fetch_with_retries(&j);
+        server.join().unwrap();
+        assert!(attempts.result.is_err());
+        assert_eq!(attempts.retries, 1);
+        assert_eq!(attempts.http_errors, vec![503, 503]);
+
+        // no time left at all
+        let (url, server) = http_server(vec![503]);
+        let j = job(
+            &url,
+            "  attempts: 5\n  initial_backoff: 0.01\n  max_backoff: 0.02\n",
+            time::Instant::now(),
+        );
+        let attempts = fetch_with_retries(&j);
+        server.join().unwrap();
+        assert_eq!(attempts.retries, 0);
+        assert_eq!(attempts.http_errors, vec![503]);
     }
 }
@@ -0,0 +1,262 @@
+use crate::http;
+
+use log::debug;
+use simple_error::bail;
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+use url::Url;
+
+// Minimal HTTP/1.1 client for scrape targets listening on a Unix domain socket, reqwest can't use them
+#[derive(Clone, Debug)]
+pub struct Client {
+    headers: reqwest::header::HeaderMap,
+    socket_path: String,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(socket_path: &str, timeout: Duration, headers: reqwest::header::HeaderMap) -> Self {
+        Client {
+            headers,
+            socket_path: socket_path.to_string(),
+            timeout,
+        }
+    }
+
+    // Only path and query of the URL are used, the host is sent as Host header
+    pub fn get(&self, url: &str) -> Result<http::Reply, Box<dyn Error>> {
+        let parsed = Url::parse(url)?;
+        let deadline = Instant::now() + self.timeout;
+
+        debug!(
+            "sending HTTP GET request to {} using socket {}",
+            url, self.socket_path
+        );
+        let mut stream = match UnixStream::connect(&self.socket_path) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(Box::new(io::Error::new(
+                    e.kind(),
+                    format!("can't connect to socket {} - {}", self.socket_path, e),
+                )))
+            }
+        };
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut target = parsed.path().to_string();
+        if let Some(q) = parsed.query() {
+            target.push('?');
+            target.push_str(q);
+        }
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            target,
+            parsed.host_str().unwrap_or("localhost")
+        );
+        for (name, value) in self.headers.iter() {
+            request.push_str(&format!("{}: {}\r\n", name, value.to_str()?));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // the connection is closed by the server after the response has been sent
+        let mut raw: Vec<u8> = Vec::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Box::new(self.timeout_error()));
+            }
+            stream.set_read_timeout(Some(remaining))?;
+            match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => raw.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    return Err(Box::new(self.timeout_error()));
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(Box::new(io::Error::new(
+                        e.kind(),
+                        format!("can't read reply from {} - {}", self.socket_path, e),
+                    )))
+                }
+            };
+        }
+
+        parse_response(&raw, url)
+    }
+
+    fn timeout_error(&self) -> io::Error {
+        io::Error::new(
+            ErrorKind::TimedOut,
+            format!("timeout while reading the reply from {}", self.socket_path),
+        )
+    }
+}
+
+fn parse_response(raw: &[u8], url: &str) -> Result<http::Reply, Box<dyn Error>> {
+    let end = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(v) => v,
+        None => bail!("incomplete HTTP reply for {}", url),
+    };
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let mut body = &raw[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    let (version, code) = match status.split_once(' ') {
+        Some(v) => v,
+        None => bail!("invalid HTTP status line \"{}\" for {}", status, url),
+    };
+    if !version.starts_with("HTTP/") {
+        bail!("invalid HTTP status line \"{}\" for {}", status, url);
+    }
+    let status_code: u16 = match code.get(..3).and_then(|c| c.parse().ok()) {
+        Some(v) => v,
+        None => bail!("invalid HTTP status line \"{}\" for {}", status, url),
+    };
+
+    let mut chunked = false;
+    let mut content_length: Option<usize> = None;
+    let mut content_encoding = String::new();
+    let mut content_type = String::new();
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some(v) => v,
+            None => continue,
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = match value.parse() {
+                Ok(v) => Some(v),
+                Err(_) => bail!("invalid Content-Length \"{}\" for {}", value, url),
+            };
+        } else if name.eq_ignore_ascii_case("transfer-encoding") && value.contains("chunked") {
+            chunked = true;
+        } else if name.eq_ignore_ascii_case("content-encoding") {
+            content_encoding = value.to_string();
+        } else if name.eq_ignore_ascii_case("content-type") {
+            content_type = value.to_string();
+        }
+    }
+
+    // without Content-Length the body ends when the connection is closed, Content-Length is
+    // ignored for chunked replies
+    let body = if chunked {
+        decode_chunked(body, url)?
+    } else {
+        if let Some(length) = content_length {
+            if length > body.len() {
+                bail!("incomplete HTTP reply for {}", url);
+            }
+            body = &body[..length];
+        }
+        body.to_vec()
+    };
+    Ok(http::Reply {
+        body,
+        content_encoding,
+        content_type,
+        status: status_code,
+        status_line: code.to_string(),
+    })
+}
+
+fn decode_chunked(mut raw: &[u8], url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut result: Vec<u8> = Vec::new();
+    loop {
+        let end = match raw.windows(2).position(|w| w == b"\r\n") {
+            Some(v) => v,
+            None => bail!("invalid chunked encoding in HTTP reply for {}", url),
+        };
+        let line = String::from_utf8_lossy(&raw[..end]);
+        // chunk extensions are ignored
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(v) => v,
+            Err(_) => bail!("invalid chunked encoding in HTTP reply for {}", url),
+        };
+        raw = &raw[end + 2..];
+        // trailers after the last chunk are ignored
+        if size == 0 {
+            return Ok(result);
+        }
+        match size.checked_add(2) {
+            Some(v) if raw.len() >= v => {}
+            _ => bail!("incomplete HTTP reply for {}", url),
+        };
+        if &raw[size..size + 2] != b"\r\n" {
+            bail!("invalid chunked encoding in HTTP reply for {}", url);
+        }
+        result.extend_from_slice(&raw[..size]);
+        raw = &raw[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "http://localhost/metrics";
+
+    fn parse(raw: &str) -> Result<http::Reply, Box<dyn Error>> {
+        parse_response(raw.as_bytes(), URL)
+    }
+
+    #[test]
+    fn content_length() {
+        let reply = parse(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Encoding: gzip\r\nContent-Length: 5\r\n\r\nup 1\ntrailing garbage",
+        )
+        .unwrap();
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.status_line, "200 OK");
+        assert_eq!(reply.content_type, "text/plain; version=0.0.4");
+        assert_eq!(reply.content_encoding, "gzip");
+        assert_eq!(reply.body, b"up 1\n".to_vec());
+    }
+
+    #[test]
+    fn body_until_connection_close() {
+        let reply = parse("HTTP/1.0 503 Service Unavailable\r\n\r\nnot ready\n").unwrap();
+        assert_eq!(reply.status, 503);
+        assert_eq!(reply.status_line, "503 Service Unavailable");
+        assert_eq!(reply.body, b"not ready\n".to_vec());
+    }
+
+    #[test]
+    fn chunked() {
+        let reply = parse(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 2\r\n\r\n5;name=value\r\nup 1\n\r\nA\r\n0123456789\r\n0\r\nX-Trailer: yes\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(reply.body, b"up 1\n0123456789".to_vec());
+    }
+
+    #[test]
+    fn invalid_replies() {
+        for raw in [
+            // no end of header
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n",
+            "HTTP/1.1\r\n\r\n",
+            "SSH-2.0 OpenSSH\r\n\r\n",
+            "HTTP/1.1 2x0 OK\r\n\r\n",
+            // short read
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nup 1\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\nup 1\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nup",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nup 1\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nx\r\nup 1\n\r\n0\r\n\r\n",
+            // chunk not terminated by CRLF
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nup 1\n\r\n0\r\n\r\n",
+            // chunk sizes that overflow
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nup 1\n\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffffffff\r\nup 1\n\r\n0\r\n\r\n",
+        ] {
+            assert!(parse(raw).is_err(), "{:?}", raw);
+        }
+    }
+}
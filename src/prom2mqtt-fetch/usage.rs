@@ -3,7 +3,7 @@ use crate::constants;
 pub fn show_usage() {
     global::usage::show_version();
     println!(
-        "Usage: {} [-c <cfg>|--config=<cfg>] [-h|--help] [-q|--quiet] [-C|--check] [-D|--debug] [-V|--version]
+        "Usage: {} [-c <cfg>|--config=<cfg>] [-h|--help] [-q|--quiet] [-C|--check [--connect]] [-D|--debug] [--dry-run|--once] [-L <module>=<level>|--log-level=<module>=<level>] [-V|--version]
 
     -c <cfg>        Read configuration from file <cfg>
     --config=<cfg>  Default: {}
@@ -17,9 +17,21 @@ pub fn show_usage() {
     -C              Check configuration file and exit
     --check
 
+    --connect       Together with --check: connect to the MQTT broker, publish a test message,
+                    fetch all scrape URLs once and print a report.
+                    Exit with a non-zero exit code if any check failed
+
     -D              Enable debug log
     --debug
 
+    --dry-run       Scrape all jobs once, print the payload and its size and exit
+                    without connecting to the MQTT broker
+
+    --once          Scrape all jobs once, publish the data and exit
+
+    -L <module>=<level>             Set log level of <module> to <level>, can be repeated
+    --log-level=<module>=<level>    e.g. -L mqtt_sender=debug -L scrape=warn
+
     -V              Show version information
     --version
 